@@ -0,0 +1,229 @@
+use crate::{
+    db::{load_hashmap, make_readable, TimeRating, DEFAULT_RATING},
+    journal::save_with_journal,
+    requests,
+};
+use rand::{rngs::ThreadRng, seq::IteratorRandom, seq::SliceRandom, thread_rng};
+use rspotify::{model::TrackId, prelude::*, AuthCodeSpotify};
+use serde::{Deserialize, Serialize};
+use std::{collections::HashMap, error::Error, io};
+
+/// Score of a song rated 'ok'
+const BASE_SCORE: f32 = 1500.;
+/// Score difference between two neighbouring ratings
+const SCORE_PER_RATING: f32 = 100.;
+/// Scores of songs with few comparisons move faster
+const K_MAX: f32 = 64.;
+const K_MIN: f32 = 16.;
+
+/// Elo score of a song, built from pairwise comparisons
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct Elo {
+    pub score: f32,
+    pub comparisons: u32,
+}
+
+impl Elo {
+    /// Seeds the score from the rating, so comparisons start from a sensible prior
+    fn seeded(rating: f32) -> Self {
+        Self {
+            score: BASE_SCORE + (DEFAULT_RATING - rating) * SCORE_PER_RATING,
+            comparisons: 0,
+        }
+    }
+
+    fn k_factor(&self) -> f32 {
+        (K_MAX / (1. + self.comparisons as f32 / 10.)).max(K_MIN)
+    }
+}
+
+/// The score of the song, seeded from its rating if it was never compared
+pub fn score(time_rating: &TimeRating) -> Elo {
+    time_rating
+        .elo
+        .clone()
+        .unwrap_or_else(|| Elo::seeded(time_rating.rating))
+}
+
+/// Converts a score to the rating scale without clamping or rounding, so scores keep their order
+pub fn unclamped_rating_from_score(score: f32) -> f32 {
+    DEFAULT_RATING - (score - BASE_SCORE) / SCORE_PER_RATING
+}
+
+/// Converts a score back to the rating scale (1 = great, 4 = bad)
+pub fn rating_from_score(score: f32) -> f32 {
+    let rating = unclamped_rating_from_score(score).clamp(1., 4.);
+    // two decimals are plenty and keep the ratings readable
+    (rating * 100.).round() / 100.
+}
+
+/// Probability of a song with score `a` winning against one with score `b`
+fn expected(a: f32, b: f32) -> f32 {
+    1. / (1. + 10f32.powf((b - a) / 400.))
+}
+
+/// Updates both scores, `result` is 1 if `a` won, 0 if `b` won and 0.5 for a draw
+fn update(a: &mut Elo, b: &mut Elo, result: f32) {
+    let expected_a = expected(a.score, b.score);
+    let (k_a, k_b) = (a.k_factor(), b.k_factor());
+
+    a.score += k_a * (result - expected_a);
+    b.score += k_b * (expected_a - result);
+    a.comparisons += 1;
+    b.comparisons += 1;
+}
+
+/// How much comparing the two songs is expected to tell us.
+/// Highest for even matches against opponents with uncertain scores
+fn information(a: &Elo, b: &Elo) -> f32 {
+    let p = expected(a.score, b.score);
+    p * (1. - p) / (1. + b.comparisons as f32)
+}
+
+/// Chooses the least compared song and the opponent that maximizes the information of the outcome
+fn choose_pair(
+    ratings: &HashMap<String, TimeRating>,
+    rng: &mut ThreadRng,
+) -> Option<(String, String)> {
+    let scores: Vec<_> = ratings
+        .iter()
+        .map(|(song_id, time_rating)| (song_id, score(time_rating)))
+        .collect();
+
+    let min_comparisons = scores.iter().map(|(_, elo)| elo.comparisons).min()?;
+    let (first, first_elo) = scores
+        .iter()
+        .filter(|(_, elo)| elo.comparisons == min_comparisons)
+        .choose(rng)?;
+
+    let mut opponents: Vec<_> = scores
+        .iter()
+        .filter(|(song_id, _)| song_id != first)
+        .collect();
+    // break ties randomly
+    opponents.shuffle(rng);
+    let (second, _) = opponents
+        .into_iter()
+        .max_by(|(_, a), (_, b)| information(first_elo, a).total_cmp(&information(first_elo, b)))?;
+
+    Some(((*first).clone(), (*second).clone()))
+}
+
+/// Asks the user to compare pairs of songs until they quit or `rounds` comparisons are done
+pub async fn compare(
+    spotify: AuthCodeSpotify,
+    db_path: String,
+    rounds: Option<usize>,
+    dry_run: bool,
+) -> Result<(), Box<dyn Error>> {
+    // get ratings db
+    let mut ratings = match load_hashmap(db_path.clone()) {
+        Ok(hashmap) => hashmap,
+        Err(e) => {
+            println!("Error loading database: {e}");
+            return Ok(());
+        }
+    };
+
+    let mut rng = thread_rng();
+    let mut compared = 0;
+    while rounds.is_none_or(|rounds| compared < rounds) {
+        let Some((a, b)) = choose_pair(&ratings, &mut rng) else {
+            println!("Not enough songs to compare");
+            break;
+        };
+
+        // get song names
+        let track_ids = [TrackId::from_id(a.as_str())?, TrackId::from_id(b.as_str())?];
+        let tracks = requests::request(|| spotify.tracks(track_ids.clone(), None)).await?;
+        let names: Vec<_> = tracks
+            .iter()
+            .map(|track| {
+                format!(
+                    "{} - {}",
+                    track.name,
+                    track
+                        .artists
+                        .iter()
+                        .map(|artist| artist.name.as_str())
+                        .collect::<Vec<_>>()
+                        .join(", ")
+                )
+            })
+            .collect();
+
+        println!("Which song is better?");
+        println!("  [1] {}", names.first().unwrap_or(&a));
+        println!("  [2] {}", names.get(1).unwrap_or(&b));
+        println!("  [=] equal  [s] skip  [q] quit");
+
+        // read input
+        let mut choice_buffer = String::new();
+        if io::stdin().read_line(&mut choice_buffer)? == 0 {
+            break;
+        }
+        let result = match choice_buffer.trim() {
+            "1" => 1.,
+            "2" => 0.,
+            "=" => 0.5,
+            "s" => continue,
+            "q" => break,
+            other => {
+                println!("Invalid choice '{other}'");
+                continue;
+            }
+        };
+
+        // apply change
+        let before = ratings.clone();
+        let mut elo_a = score(&ratings[&a]);
+        let mut elo_b = score(&ratings[&b]);
+        update(&mut elo_a, &mut elo_b, result);
+        println!("{:.0} / {:.0}", elo_a.score, elo_b.score);
+        ratings.get_mut(&a).expect("song is in the database").elo = Some(elo_a);
+        ratings.get_mut(&b).expect("song is in the database").elo = Some(elo_b);
+
+        save_with_journal(db_path.clone(), "compare", &before, &ratings, dry_run)?;
+        compared += 1;
+    }
+
+    println!("{compared} comparisons recorded");
+
+    Ok(())
+}
+
+/// Overwrites the ratings of all compared songs with the rating derived from their score
+pub fn apply_ratings(db_path: String, dry_run: bool) -> Result<(), Box<dyn Error>> {
+    // get ratings db
+    let mut ratings = match load_hashmap(db_path.clone()) {
+        Ok(hashmap) => hashmap,
+        Err(e) => {
+            println!("Error loading database: {e}");
+            return Ok(());
+        }
+    };
+
+    let before = ratings.clone();
+    for (song_id, time_rating) in ratings.iter_mut() {
+        let Some(elo) = &time_rating.elo else {
+            continue;
+        };
+        let rating = rating_from_score(elo.score);
+        if rating != time_rating.rating {
+            println!(
+                "{song_id}: {} -> {}",
+                make_readable(time_rating.rating),
+                make_readable(rating)
+            );
+            time_rating.rate(rating);
+        }
+    }
+
+    save_with_journal(
+        db_path,
+        "compare --apply-ratings",
+        &before,
+        &ratings,
+        dry_run,
+    )
+}
@@ -0,0 +1,354 @@
+use crate::requests;
+use base64::{engine::general_purpose, Engine};
+use image::{codecs::jpeg::JpegEncoder, imageops::FilterType, DynamicImage, RgbImage};
+use rspotify::{
+    http::HttpError,
+    model::{PlaylistId, TrackId},
+    prelude::*,
+    AuthCodeSpotify,
+};
+use std::error::Error;
+
+/// Width and height of generated covers in pixels
+const COVER_SIZE: u32 = 640;
+/// Maximum size of an uploaded cover (base64 encoded), given by the spotify API docs
+const MAX_COVER_BYTES: usize = 256 * 1024;
+/// Maximum amount of tracks per request, given by the spotify API docs
+const TRACKS_BATCH_SIZE: usize = 50;
+/// JPEG qualities tried in order, until the cover is small enough
+const QUALITIES: [u8; 6] = [90, 80, 70, 60, 50, 40];
+
+/// The requests of a cover generation, so it can run against a fake client
+pub trait CoverClient {
+    /// Urls of the album arts of at most `TRACKS_BATCH_SIZE` songs, in order
+    async fn album_arts(
+        &self,
+        song_ids: &[TrackId<'static>],
+    ) -> Result<Vec<String>, Box<dyn Error>>;
+
+    async fn fetch_image(&self, url: &str) -> Result<Vec<u8>, Box<dyn Error>>;
+
+    async fn upload_cover(
+        &self,
+        playlist_id: &PlaylistId<'static>,
+        jpeg: &[u8],
+    ) -> Result<(), Box<dyn Error>>;
+}
+
+/// Fetches album arts and uploads covers with the authentification of the spotify client
+pub struct SpotifyCoverClient<'a> {
+    spotify: &'a AuthCodeSpotify,
+    http: reqwest::Client,
+}
+
+impl<'a> SpotifyCoverClient<'a> {
+    pub fn new(spotify: &'a AuthCodeSpotify) -> Self {
+        Self {
+            spotify,
+            http: reqwest::Client::new(),
+        }
+    }
+}
+
+impl CoverClient for SpotifyCoverClient<'_> {
+    async fn album_arts(
+        &self,
+        song_ids: &[TrackId<'static>],
+    ) -> Result<Vec<String>, Box<dyn Error>> {
+        Ok(
+            requests::request(|| self.spotify.tracks(song_ids.iter().cloned(), None))
+                .await?
+                .into_iter()
+                // the largest image comes first
+                .filter_map(|track| track.album.images.first().map(|image| image.url.clone()))
+                .collect(),
+        )
+    }
+
+    async fn fetch_image(&self, url: &str) -> Result<Vec<u8>, Box<dyn Error>> {
+        Ok(self
+            .http
+            .get(url)
+            .send()
+            .await?
+            .error_for_status()?
+            .bytes()
+            .await?
+            .to_vec())
+    }
+
+    async fn upload_cover(
+        &self,
+        playlist_id: &PlaylistId<'static>,
+        jpeg: &[u8],
+    ) -> Result<(), Box<dyn Error>> {
+        let url = format!(
+            "https://api.spotify.com/v1/playlists/{}/images",
+            playlist_id.id()
+        );
+        let body = general_purpose::STANDARD.encode(jpeg);
+
+        // not supported by rspotify, but it takes care of the token.
+        // Errors are turned into rspotify's, so the shared policy handles them like any other request
+        requests::request(|| async {
+            let mut request = self.http.put(&url);
+            for (name, value) in self.spotify.auth_headers().await {
+                request = request.header(name, value);
+            }
+            let response = request
+                .header("Content-Type", "image/jpeg")
+                .body(body.clone())
+                .send()
+                .await
+                .map_err(HttpError::from)?;
+            if !response.status().is_success() {
+                return Err(HttpError::StatusCode(response).into());
+            }
+
+            Ok(())
+        })
+        .await?;
+
+        Ok(())
+    }
+}
+
+/// Builds a cover from the album arts of the first songs and uploads it.
+/// The cover is a grid of the first four different album arts, or the first one if there are fewer
+pub async fn set_cover(
+    client: &impl CoverClient,
+    playlist_id: &PlaylistId<'static>,
+    song_ids: &[TrackId<'static>],
+    dry_run: bool,
+) -> Result<(), Box<dyn Error>> {
+    let mut urls = Vec::new();
+    // the songs of the first request are enough for most playlists
+    for chunk in song_ids.chunks(TRACKS_BATCH_SIZE) {
+        for url in client.album_arts(chunk).await? {
+            if !urls.contains(&url) {
+                urls.push(url);
+            }
+        }
+        if urls.len() >= 4 {
+            break;
+        }
+    }
+    if urls.is_empty() {
+        println!("No album arts to build the cover from");
+        return Ok(());
+    }
+    let urls = if urls.len() >= 4 {
+        &urls[..4]
+    } else {
+        &urls[..1]
+    };
+
+    let mut arts = Vec::new();
+    for url in urls {
+        arts.push(image::load_from_memory(&client.fetch_image(url).await?)?);
+    }
+    let jpeg = encode_cover(&build_cover(&arts))?;
+
+    if dry_run {
+        println!(
+            "Dry run, not uploading the cover of {} album arts ({} KB)",
+            arts.len(),
+            jpeg.len() / 1024
+        );
+        return Ok(());
+    }
+
+    client.upload_cover(playlist_id, &jpeg).await?;
+    println!("Cover uploaded");
+
+    Ok(())
+}
+
+/// A 2x2 grid of four arts, or the single art scaled to the cover size
+fn build_cover(arts: &[DynamicImage]) -> RgbImage {
+    if arts.len() < 4 {
+        return arts[0]
+            .resize_to_fill(COVER_SIZE, COVER_SIZE, FilterType::Lanczos3)
+            .to_rgb8();
+    }
+
+    let tile_size = COVER_SIZE / 2;
+    let mut cover = RgbImage::new(COVER_SIZE, COVER_SIZE);
+    for (index, art) in arts.iter().take(4).enumerate() {
+        let tile = art
+            .resize_to_fill(tile_size, tile_size, FilterType::Lanczos3)
+            .to_rgb8();
+        let x = (index as u32 % 2) * tile_size;
+        let y = (index as u32 / 2) * tile_size;
+        image::imageops::replace(&mut cover, &tile, x.into(), y.into());
+    }
+
+    cover
+}
+
+/// Encodes the cover as JPEG, lowering the quality and then the size until it fits the upload limit
+fn encode_cover(cover: &RgbImage) -> Result<Vec<u8>, Box<dyn Error>> {
+    for size in [COVER_SIZE, COVER_SIZE * 3 / 4, COVER_SIZE / 2] {
+        let scaled = if size == cover.width() {
+            cover.clone()
+        } else {
+            image::imageops::resize(cover, size, size, FilterType::Triangle)
+        };
+        for quality in QUALITIES {
+            let mut jpeg = Vec::new();
+            JpegEncoder::new_with_quality(&mut jpeg, quality).encode_image(&scaled)?;
+            // base64 takes 4 bytes for every 3
+            if jpeg.len().div_ceil(3) * 4 <= MAX_COVER_BYTES {
+                return Ok(jpeg);
+            }
+        }
+    }
+
+    Err("cover is too large, even at the lowest quality and size".into())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::{playlist_id, song_ids};
+    use image::Rgb;
+    use rand::{rngs::StdRng, Rng, SeedableRng};
+    use std::cell::RefCell;
+
+    /// Serves generated album arts and keeps the uploaded cover
+    #[derive(Default)]
+    struct FakeCoverClient {
+        /// Color of the album art of each song
+        colors: Vec<[u8; 3]>,
+        noisy: bool,
+        uploaded: RefCell<Option<Vec<u8>>>,
+    }
+
+    impl CoverClient for FakeCoverClient {
+        async fn album_arts(
+            &self,
+            song_ids: &[TrackId<'static>],
+        ) -> Result<Vec<String>, Box<dyn Error>> {
+            Ok(song_ids
+                .iter()
+                .map(|song_id| song_id.id().parse::<usize>().unwrap())
+                .filter_map(|index| self.colors.get(index))
+                .map(|[r, g, b]| format!("{r},{g},{b}"))
+                .collect())
+        }
+
+        async fn fetch_image(&self, url: &str) -> Result<Vec<u8>, Box<dyn Error>> {
+            let color: Vec<u8> = url.split(',').map(|part| part.parse().unwrap()).collect();
+            let mut rng = StdRng::seed_from_u64(0);
+            let art = RgbImage::from_fn(640, 640, |_, _| {
+                if self.noisy {
+                    Rgb(rng.gen())
+                } else {
+                    Rgb([color[0], color[1], color[2]])
+                }
+            });
+            let mut jpeg = Vec::new();
+            JpegEncoder::new_with_quality(&mut jpeg, 100).encode_image(&art)?;
+            Ok(jpeg)
+        }
+
+        async fn upload_cover(
+            &self,
+            _: &PlaylistId<'static>,
+            jpeg: &[u8],
+        ) -> Result<(), Box<dyn Error>> {
+            *self.uploaded.borrow_mut() = Some(jpeg.to_vec());
+            Ok(())
+        }
+    }
+
+    fn uploaded(client: &FakeCoverClient) -> RgbImage {
+        let jpeg = client
+            .uploaded
+            .borrow()
+            .clone()
+            .expect("cover was uploaded");
+        assert!(general_purpose::STANDARD.encode(&jpeg).len() <= MAX_COVER_BYTES);
+        image::load_from_memory(&jpeg).unwrap().to_rgb8()
+    }
+
+    fn assert_color(cover: &RgbImage, x: u32, y: u32, color: [u8; 3]) {
+        let pixel = cover.get_pixel(x, y).0;
+        assert!(
+            pixel.iter().zip(color).all(|(a, b)| a.abs_diff(b) < 10),
+            "{pixel:?} at {x}, {y} isn't {color:?}"
+        );
+    }
+
+    #[tokio::test]
+    async fn builds_grid_of_different_album_arts() {
+        let red = [255, 0, 0];
+        let green = [0, 255, 0];
+        let blue = [0, 0, 255];
+        let white = [255, 255, 255];
+        let client = FakeCoverClient {
+            colors: vec![red, red, green, blue, green, white, blue],
+            ..FakeCoverClient::default()
+        };
+
+        set_cover(&client, &playlist_id(), &song_ids(0..7), false)
+            .await
+            .unwrap();
+
+        let cover = uploaded(&client);
+        assert_eq!(cover.dimensions(), (COVER_SIZE, COVER_SIZE));
+        assert_color(&cover, 100, 100, red);
+        assert_color(&cover, 540, 100, green);
+        assert_color(&cover, 100, 540, blue);
+        assert_color(&cover, 540, 540, white);
+    }
+
+    #[tokio::test]
+    async fn uses_single_album_art_if_there_are_few() {
+        let client = FakeCoverClient {
+            colors: vec![[0, 0, 255], [0, 0, 255], [255, 0, 0]],
+            ..FakeCoverClient::default()
+        };
+
+        set_cover(&client, &playlist_id(), &song_ids(0..3), false)
+            .await
+            .unwrap();
+
+        let cover = uploaded(&client);
+        assert_color(&cover, 100, 100, [0, 0, 255]);
+        assert_color(&cover, 540, 540, [0, 0, 255]);
+    }
+
+    #[tokio::test]
+    async fn stays_under_size_limit() {
+        let client = FakeCoverClient {
+            colors: vec![[0, 0, 0]; 4],
+            noisy: true,
+            ..FakeCoverClient::default()
+        };
+
+        set_cover(&client, &playlist_id(), &song_ids(0..4), false)
+            .await
+            .unwrap();
+
+        uploaded(&client);
+    }
+
+    #[tokio::test]
+    async fn skips_dry_run_and_missing_arts() {
+        let client = FakeCoverClient {
+            colors: vec![[0, 0, 0]; 4],
+            ..FakeCoverClient::default()
+        };
+        set_cover(&client, &playlist_id(), &song_ids(0..4), true)
+            .await
+            .unwrap();
+        assert!(client.uploaded.borrow().is_none());
+
+        let client = FakeCoverClient::default();
+        set_cover(&client, &playlist_id(), &song_ids(0..4), false)
+            .await
+            .unwrap();
+        assert!(client.uploaded.borrow().is_none());
+    }
+}
@@ -0,0 +1,176 @@
+use crate::compare::Elo;
+use chrono::{DateTime, Utc};
+use rspotify::{model::FullTrack, prelude::*};
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::HashMap,
+    error::Error,
+    fs::{create_dir_all, File},
+    io::{BufReader, Read, Write},
+    path::Path,
+};
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct TimeRating {
+    /// When the song was liked, `None` if it was added from a playlist or album without being liked
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub added_at: Option<DateTime<Utc>>,
+    pub rating: f32,
+    /// When the song was last rated, `None` if it never was
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub rated_at: Option<DateTime<Utc>>,
+    /// Score from pairwise comparisons, `None` if the song was never compared
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub elo: Option<Elo>,
+    /// Name, artists and album of the song, `None` until the next update-db
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub metadata: Option<TrackMetadata>,
+    /// Ratings in named contexts like 'workout', `rating` is the one of the default context
+    #[serde(default, skip_serializing_if = "HashMap::is_empty")]
+    pub contexts: HashMap<String, f32>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct TrackMetadata {
+    pub name: String,
+    pub artists: Vec<String>,
+    pub artist_ids: Vec<String>,
+    pub album: String,
+    pub album_id: Option<String>,
+}
+
+impl From<&FullTrack> for TrackMetadata {
+    fn from(track: &FullTrack) -> Self {
+        Self {
+            name: track.name.clone(),
+            artists: track
+                .artists
+                .iter()
+                .map(|artist| artist.name.clone())
+                .collect(),
+            artist_ids: track
+                .artists
+                .iter()
+                .filter_map(|artist| artist.id.as_ref().map(|id| id.id().to_string()))
+                .collect(),
+            album: track.album.name.clone(),
+            album_id: track.album.id.as_ref().map(|id| id.id().to_string()),
+        }
+    }
+}
+
+/// Rating of songs that were never rated
+pub const DEFAULT_RATING: f32 = 3.;
+
+impl TimeRating {
+    pub fn new(added_at: Option<DateTime<Utc>>, rating: f32) -> Self {
+        Self {
+            added_at,
+            rating,
+            rated_at: None,
+            elo: None,
+            metadata: None,
+            contexts: HashMap::new(),
+        }
+    }
+
+    /// Applies the rating and remembers when it was given
+    pub fn rate(&mut self, rating: f32) {
+        self.rating = rating;
+        self.rated_at = Some(Utc::now());
+    }
+
+    /// Songs from databases predating `rated_at` count as rated if they differ from the default
+    pub fn is_unrated(&self) -> bool {
+        self.rated_at.is_none() && self.rating == DEFAULT_RATING
+    }
+
+    /// Rating in the context, falling back to the default rating if the song wasn't rated in it
+    pub fn rating_in(&self, context: Option<&str>) -> f32 {
+        context
+            .and_then(|context| self.contexts.get(context))
+            .copied()
+            .unwrap_or(self.rating)
+    }
+
+    /// Applies the rating in the context, or the default one if `None`.
+    /// `rated_at` only tracks the default context
+    pub fn rate_in(&mut self, context: Option<&str>, rating: f32) {
+        match context {
+            Some(context) => {
+                self.contexts.insert(context.to_string(), rating);
+            }
+            None => self.rate(rating),
+        }
+    }
+
+    pub fn is_unrated_in(&self, context: Option<&str>) -> bool {
+        match context {
+            Some(context) => !self.contexts.contains_key(context),
+            None => self.is_unrated(),
+        }
+    }
+}
+
+/// Parses a rating name or number
+pub fn parse_rating(input: &str) -> Option<f32> {
+    match input {
+        "great" | "1" => Some(1.),
+        "good" | "2" => Some(2.),
+        "ok" | "3" => Some(3.),
+        "bad" | "4" => Some(4.),
+        other => other
+            .parse::<f32>()
+            .ok()
+            .filter(|rating| rating.is_finite()),
+    }
+}
+
+pub fn make_readable(input: f32) -> String {
+    match input {
+        1.0 => "great".to_string(),
+        2.0 => "good".to_string(),
+        3.0 => "ok".to_string(),
+        4.0 => "bad".to_string(),
+        other => other.to_string(),
+    }
+}
+
+pub fn load_or_create_hashmap(
+    file_path: String,
+) -> Result<HashMap<String, TimeRating>, Box<dyn Error>> {
+    load_hashmap(file_path).map_or(Ok(HashMap::new()), Ok)
+}
+
+pub fn load_hashmap(file_path: String) -> Result<HashMap<String, TimeRating>, Box<dyn Error>> {
+    match File::open(file_path) {
+        Ok(file) => {
+            // If the file exists, attempt to read from it
+            let mut file_contents = String::new();
+            let mut buf_reader = BufReader::new(file);
+            buf_reader.read_to_string(&mut file_contents)?;
+            // Deserialize JSON to HashMap
+            Ok(serde_json::from_str(&file_contents)?)
+        }
+        // If the file doesnt exist, create a new HashMap
+        Err(e) => Err(Box::new(e)),
+    }
+}
+
+pub fn save_hashmap(
+    file_path: String,
+    hashmap: &HashMap<String, TimeRating>,
+) -> Result<(), Box<dyn Error>> {
+    let serialized_hashmap = serde_json::to_string(hashmap)?;
+
+    // Extract the parent directory from the provided file path
+    if let Some(parent) = Path::new(&file_path).parent() {
+        create_dir_all(parent)?; // Create the directory structure if it does not exist
+    }
+
+    let mut file = File::create(file_path)?;
+
+    file.write_all(serialized_hashmap.as_bytes())?;
+
+    Ok(())
+}
@@ -0,0 +1,86 @@
+use crate::db::TrackMetadata;
+use std::collections::HashMap;
+
+/// Spacing constraints for generated orderings, songs without metadata are never constrained
+#[derive(Debug, Clone, Copy, Default)]
+pub struct DiversityRules {
+    /// No artist repeats within this many songs
+    pub artist_spacing: Option<usize>,
+    /// Maximum amount of songs of the same album
+    pub max_per_album: Option<usize>,
+}
+
+impl DiversityRules {
+    /// Whether the candidate may follow the already placed songs without an artist repeating too soon
+    pub fn spacing_allows(
+        &self,
+        placed: &[Option<&TrackMetadata>],
+        candidate: Option<&TrackMetadata>,
+    ) -> bool {
+        let (Some(spacing), Some(candidate)) = (self.artist_spacing, candidate) else {
+            return true;
+        };
+
+        placed[placed.len().saturating_sub(spacing)..]
+            .iter()
+            .flatten()
+            .all(|metadata| {
+                !metadata
+                    .artist_ids
+                    .iter()
+                    .any(|artist_id| candidate.artist_ids.contains(artist_id))
+            })
+    }
+
+    /// Whether the album of the candidate is still below the limit
+    pub fn album_allows(
+        &self,
+        album_counts: &HashMap<String, usize>,
+        candidate: Option<&TrackMetadata>,
+    ) -> bool {
+        match (
+            self.max_per_album,
+            candidate.and_then(|metadata| metadata.album_id.as_ref()),
+        ) {
+            (Some(max_per_album), Some(album_id)) => {
+                album_counts.get(album_id).copied().unwrap_or_default() < max_per_album
+            }
+            _ => true,
+        }
+    }
+
+    /// Returns the indices of the songs in their new order.
+    /// Songs are moved back as little as needed for the artist spacing, songs over the album limit are dropped.
+    /// If no remaining song fits the spacing, the spacing is relaxed rather than dropping songs
+    pub fn arrange(&self, metadata: &[Option<&TrackMetadata>]) -> Vec<usize> {
+        let mut pending: Vec<usize> = (0..metadata.len()).collect();
+        let mut order = Vec::with_capacity(metadata.len());
+        let mut placed = Vec::with_capacity(metadata.len());
+        let mut album_counts = HashMap::new();
+
+        // drop songs over the album limit, in their original order
+        pending.retain(|&index| {
+            let allowed = self.album_allows(&album_counts, metadata[index]);
+            if allowed {
+                if let Some(album_id) =
+                    metadata[index].and_then(|metadata| metadata.album_id.clone())
+                {
+                    *album_counts.entry(album_id).or_default() += 1;
+                }
+            }
+            allowed
+        });
+
+        while !pending.is_empty() {
+            let position = pending
+                .iter()
+                .position(|&index| self.spacing_allows(&placed, metadata[index]))
+                .unwrap_or(0);
+            let index = pending.remove(position);
+            order.push(index);
+            placed.push(metadata[index]);
+        }
+
+        order
+    }
+}
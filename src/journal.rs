@@ -0,0 +1,254 @@
+use crate::db::{load_hashmap, make_readable, save_hashmap, TimeRating};
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::HashMap,
+    error::Error,
+    fs::{create_dir_all, File},
+    io::{BufReader, ErrorKind, Write},
+    path::{Path, PathBuf},
+};
+
+/// Maximum amount of operations kept in the journal
+const JOURNAL_LIMIT: usize = 100;
+
+/// The change of a single song in the database
+#[derive(Serialize, Deserialize, Debug)]
+struct Change {
+    track_id: String,
+    /// `None` if the song was inserted
+    before: Option<TimeRating>,
+    /// `None` if the song was removed
+    after: Option<TimeRating>,
+}
+
+/// A mutating operation on the database, like a rate or update-db
+#[derive(Serialize, Deserialize, Debug)]
+struct Operation {
+    description: String,
+    time: DateTime<Utc>,
+    changes: Vec<Change>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Default)]
+struct Journal {
+    undo: Vec<Operation>,
+    redo: Vec<Operation>,
+}
+
+/// The journal is stored next to the database, e.g. 'ratings.json' -> 'ratings.journal.json'
+fn journal_path(db_path: &str) -> PathBuf {
+    Path::new(db_path).with_extension("journal.json")
+}
+
+fn load_journal(db_path: &str) -> Result<Journal, Box<dyn Error>> {
+    match File::open(journal_path(db_path)) {
+        Ok(file) => Ok(serde_json::from_reader(BufReader::new(file))?),
+        // no journal yet
+        Err(e) if e.kind() == ErrorKind::NotFound => Ok(Journal::default()),
+        Err(e) => Err(Box::new(e)),
+    }
+}
+
+fn save_journal(db_path: &str, journal: &Journal) -> Result<(), Box<dyn Error>> {
+    let path = journal_path(db_path);
+
+    if let Some(parent) = path.parent() {
+        create_dir_all(parent)?;
+    }
+
+    File::create(path)?.write_all(serde_json::to_string(journal)?.as_bytes())?;
+
+    Ok(())
+}
+
+/// Collects all songs that differ between the two databases
+fn diff(before: &HashMap<String, TimeRating>, after: &HashMap<String, TimeRating>) -> Vec<Change> {
+    let mut changes: Vec<_> = after
+        .iter()
+        .filter(|(track_id, time_rating)| before.get(*track_id) != Some(time_rating))
+        .map(|(track_id, time_rating)| Change {
+            track_id: track_id.clone(),
+            before: before.get(track_id).cloned(),
+            after: Some(time_rating.clone()),
+        })
+        .chain(
+            before
+                .iter()
+                .filter(|(track_id, _)| !after.contains_key(*track_id))
+                .map(|(track_id, time_rating)| Change {
+                    track_id: track_id.clone(),
+                    before: Some(time_rating.clone()),
+                    after: None,
+                }),
+        )
+        .collect();
+    changes.sort_by(|a, b| a.track_id.cmp(&b.track_id));
+
+    changes
+}
+
+/// Describes the change of a single song from `before` to `after`, e.g. 'ok -> great'
+fn describe(before: Option<&TimeRating>, after: Option<&TimeRating>) -> String {
+    let (before, after) = match (before, after) {
+        (None, Some(after)) => return format!("added as {}", make_readable(after.rating)),
+        (Some(_), None) => return "removed".to_string(),
+        (Some(before), Some(after)) => (before, after),
+        (None, None) => unreachable!("changes have a before or after"),
+    };
+
+    let mut parts = Vec::new();
+    if before.rating != after.rating {
+        parts.push(format!(
+            "{} -> {}",
+            make_readable(before.rating),
+            make_readable(after.rating)
+        ));
+    }
+    let mut contexts: Vec<_> = before
+        .contexts
+        .keys()
+        .chain(after.contexts.keys())
+        .collect();
+    contexts.sort();
+    contexts.dedup();
+    for context in contexts {
+        let (old, new) = (before.contexts.get(context), after.contexts.get(context));
+        if old != new {
+            let readable = |rating: Option<&f32>| {
+                rating.map_or("-".to_string(), |rating| make_readable(*rating))
+            };
+            parts.push(format!("{context}: {} -> {}", readable(old), readable(new)));
+        }
+    }
+    if before.elo != after.elo {
+        let score = |time_rating: &TimeRating| {
+            time_rating
+                .elo
+                .as_ref()
+                .map_or("-".to_string(), |elo| format!("{:.0}", elo.score))
+        };
+        parts.push(format!("score {} -> {}", score(before), score(after)));
+    }
+    if before.metadata != after.metadata {
+        parts.push("metadata updated".to_string());
+    }
+    if parts.is_empty() {
+        parts.push("updated".to_string());
+    }
+
+    parts.join(", ")
+}
+
+/// Saves the database and records its changes since `before` in the journal.
+/// If `dry_run` is set, only prints the changes
+pub fn save_with_journal(
+    db_path: String,
+    description: &str,
+    before: &HashMap<String, TimeRating>,
+    after: &HashMap<String, TimeRating>,
+    dry_run: bool,
+) -> Result<(), Box<dyn Error>> {
+    let changes = diff(before, after);
+
+    if dry_run {
+        println!(
+            "Dry run, not saving {} changes of '{description}'",
+            changes.len()
+        );
+        for change in &changes {
+            println!(
+                "  {}: {}",
+                change.track_id,
+                describe(change.before.as_ref(), change.after.as_ref())
+            );
+        }
+        return Ok(());
+    }
+
+    save_hashmap(db_path.clone(), after)?;
+
+    if changes.is_empty() {
+        return Ok(());
+    }
+
+    let mut journal = load_journal(&db_path)?;
+    journal.undo.push(Operation {
+        description: description.to_string(),
+        time: Utc::now(),
+        changes,
+    });
+    // forget the oldest operations
+    let overflow = journal.undo.len().saturating_sub(JOURNAL_LIMIT);
+    journal.undo.drain(..overflow);
+    // a new operation invalidates the undone ones
+    journal.redo.clear();
+
+    save_journal(&db_path, &journal)
+}
+
+/// Reverts the last `steps` operations
+pub fn undo(db_path: String, steps: usize, dry_run: bool) -> Result<(), Box<dyn Error>> {
+    replay(db_path, steps, true, dry_run)
+}
+
+/// Reapplies the last `steps` undone operations
+pub fn redo(db_path: String, steps: usize, dry_run: bool) -> Result<(), Box<dyn Error>> {
+    replay(db_path, steps, false, dry_run)
+}
+
+fn replay(db_path: String, steps: usize, undo: bool, dry_run: bool) -> Result<(), Box<dyn Error>> {
+    let mut journal = load_journal(&db_path)?;
+    let mut ratings = load_hashmap(db_path.clone())?;
+
+    let (from, to) = if undo {
+        (&mut journal.undo, &mut journal.redo)
+    } else {
+        (&mut journal.redo, &mut journal.undo)
+    };
+
+    for _ in 0..steps {
+        let Some(operation) = from.pop() else {
+            println!("Nothing left to {}", if undo { "undo" } else { "redo" });
+            break;
+        };
+
+        println!(
+            "{} '{}' from {}",
+            if undo { "Undoing" } else { "Redoing" },
+            operation.description,
+            operation.time.format("%Y-%m-%d %H:%M")
+        );
+
+        for change in &operation.changes {
+            let (replaced, restored) = if undo {
+                (&change.after, &change.before)
+            } else {
+                (&change.before, &change.after)
+            };
+            println!(
+                "  {}: {}",
+                change.track_id,
+                describe(replaced.as_ref(), restored.as_ref())
+            );
+            match restored {
+                Some(time_rating) => {
+                    ratings.insert(change.track_id.clone(), time_rating.clone());
+                }
+                None => {
+                    ratings.remove(&change.track_id);
+                }
+            }
+        }
+
+        to.push(operation);
+    }
+
+    if dry_run {
+        println!("Dry run, not saving the database and journal");
+        return Ok(());
+    }
+
+    save_hashmap(db_path.clone(), &ratings)?;
+    save_journal(&db_path, &journal)
+}
@@ -0,0 +1,315 @@
+use crate::{
+    db::{load_hashmap, TimeRating, TrackMetadata},
+    refs,
+};
+use rspotify::prelude::*;
+use serde::{Deserialize, Serialize};
+use std::{
+    cmp::Reverse,
+    collections::HashMap,
+    error::Error,
+    fs::{create_dir_all, File},
+    io::{BufReader, ErrorKind, Write},
+    path::{Path, PathBuf},
+};
+
+/// Whether a song is always or never wanted
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Listed {
+    /// Always included in generated playlists, with the highest weight
+    Pinned,
+    /// Never included in generated playlists, with a weight of 0
+    Banned,
+}
+
+impl Listed {
+    pub fn name(self) -> &'static str {
+        match self {
+            Listed::Pinned => "pinned",
+            Listed::Banned => "banned",
+        }
+    }
+}
+
+/// The tracks and artists of a list
+#[derive(Serialize, Deserialize, Debug, Default)]
+pub struct Entries {
+    #[serde(default)]
+    pub tracks: Vec<String>,
+    #[serde(default)]
+    pub artists: Vec<String>,
+}
+
+impl Entries {
+    fn contains(&self, song_id: &str, metadata: Option<&TrackMetadata>) -> bool {
+        self.tracks.iter().any(|track_id| track_id == song_id)
+            || metadata.is_some_and(|metadata| {
+                metadata
+                    .artist_ids
+                    .iter()
+                    .any(|artist_id| self.artists.contains(artist_id))
+            })
+    }
+
+    fn is_empty(&self) -> bool {
+        self.tracks.is_empty() && self.artists.is_empty()
+    }
+}
+
+/// The pinned and banned tracks and artists
+#[derive(Serialize, Deserialize, Debug, Default)]
+pub struct Lists {
+    #[serde(default)]
+    pub pinned: Entries,
+    #[serde(default)]
+    pub banned: Entries,
+}
+
+impl Lists {
+    /// Whether the song is pinned or banned, by itself or by one of its artists.
+    /// Bans win over pins
+    pub fn status(&self, song_id: &str, metadata: Option<&TrackMetadata>) -> Option<Listed> {
+        if self.banned.contains(song_id, metadata) {
+            Some(Listed::Banned)
+        } else if self.pinned.contains(song_id, metadata) {
+            Some(Listed::Pinned)
+        } else {
+            None
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.pinned.is_empty() && self.banned.is_empty()
+    }
+
+    /// The pinned tracks, followed by the songs of the database by pinned artists (newest first),
+    /// without banned songs
+    pub fn pinned_songs(&self, ratings: &HashMap<String, TimeRating>) -> Vec<String> {
+        let mut by_artist: Vec<_> = ratings
+            .iter()
+            .filter(|(song_id, _)| !self.pinned.tracks.contains(song_id))
+            .filter(|(_, time_rating)| {
+                time_rating.metadata.as_ref().is_some_and(|metadata| {
+                    metadata
+                        .artist_ids
+                        .iter()
+                        .any(|artist_id| self.pinned.artists.contains(artist_id))
+                })
+            })
+            .collect();
+        by_artist.sort_by_key(|(_, time_rating)| Reverse(time_rating.added_at));
+
+        self.pinned
+            .tracks
+            .iter()
+            .chain(by_artist.into_iter().map(|(song_id, _)| song_id))
+            .filter(|song_id| {
+                let metadata = ratings
+                    .get(*song_id)
+                    .and_then(|time_rating| time_rating.metadata.as_ref());
+                !self.banned.contains(song_id, metadata)
+            })
+            .cloned()
+            .collect()
+    }
+
+    fn entries(&self, listed: Listed) -> &Entries {
+        match listed {
+            Listed::Pinned => &self.pinned,
+            Listed::Banned => &self.banned,
+        }
+    }
+
+    fn ids_mut(&mut self, listed: Listed, artists: bool) -> &mut Vec<String> {
+        let entries = match listed {
+            Listed::Pinned => &mut self.pinned,
+            Listed::Banned => &mut self.banned,
+        };
+        if artists {
+            &mut entries.artists
+        } else {
+            &mut entries.tracks
+        }
+    }
+}
+
+/// Takes the first `amount` songs of the order, keeping all pinned songs even if they come later
+pub fn select(order: Vec<usize>, amount: usize, is_pinned: impl Fn(usize) -> bool) -> Vec<usize> {
+    let pinned = order.iter().filter(|&&index| is_pinned(index)).count();
+    let mut unpinned = amount.saturating_sub(pinned);
+
+    order
+        .into_iter()
+        .filter(|&index| {
+            if is_pinned(index) {
+                return true;
+            }
+            let selected = unpinned > 0;
+            unpinned = unpinned.saturating_sub(1);
+            selected
+        })
+        .collect()
+}
+
+/// The lists are stored next to the database, e.g. 'ratings.json' -> 'ratings.lists.json'
+fn lists_path(db_path: &str) -> PathBuf {
+    Path::new(db_path).with_extension("lists.json")
+}
+
+pub fn load_lists(db_path: &str) -> Result<Lists, Box<dyn Error>> {
+    match File::open(lists_path(db_path)) {
+        Ok(file) => Ok(serde_json::from_reader(BufReader::new(file))?),
+        // nothing pinned or banned yet
+        Err(e) if e.kind() == ErrorKind::NotFound => Ok(Lists::default()),
+        Err(e) => Err(Box::new(e)),
+    }
+}
+
+fn save_lists(db_path: &str, lists: &Lists) -> Result<(), Box<dyn Error>> {
+    let path = lists_path(db_path);
+
+    if let Some(parent) = path.parent() {
+        create_dir_all(parent)?;
+    }
+
+    File::create(path)?.write_all(serde_json::to_string(lists)?.as_bytes())?;
+
+    Ok(())
+}
+
+/// Adds the references to the list, removes them, or prints the list if there are neither.
+/// The references are artists if `artists` is set, tracks otherwise
+pub fn list(
+    db_path: String,
+    listed: Listed,
+    add: &[String],
+    remove: &[String],
+    artists: bool,
+    dry_run: bool,
+) -> Result<(), Box<dyn Error>> {
+    let mut lists = load_lists(&db_path)?;
+    // only used for names, so a missing database is fine
+    let ratings = load_hashmap(db_path.clone()).unwrap_or_default();
+
+    if add.is_empty() && remove.is_empty() {
+        print_entries(listed, lists.entries(listed), &ratings);
+        return Ok(());
+    }
+
+    let other = match listed {
+        Listed::Pinned => Listed::Banned,
+        Listed::Banned => Listed::Pinned,
+    };
+    for (reference, adding) in add
+        .iter()
+        .map(|reference| (reference, true))
+        .chain(remove.iter().map(|reference| (reference, false)))
+    {
+        let id = if artists {
+            refs::parse_artist_ref(reference).map(|id| id.id().to_string())
+        } else {
+            refs::parse_track_ref(reference).map(|id| id.id().to_string())
+        };
+        let Some(id) = id else {
+            println!(
+                "Invalid {} reference '{reference}'",
+                if artists { "artist" } else { "track" }
+            );
+            continue;
+        };
+        let name = if artists {
+            artist_name(&id, &ratings)
+        } else {
+            track_name(&id, &ratings)
+        };
+
+        if adding {
+            if lists.ids_mut(listed, artists).contains(&id) {
+                println!("{name}: already {}", listed.name());
+                continue;
+            }
+            // a song can't be both pinned and banned
+            let other_ids = lists.ids_mut(other, artists);
+            if let Some(position) = other_ids.iter().position(|other_id| *other_id == id) {
+                other_ids.remove(position);
+                println!("{name}: no longer {}", other.name());
+            }
+            lists.ids_mut(listed, artists).push(id);
+            println!("{name}: {}", listed.name());
+        } else {
+            let listed_ids = lists.ids_mut(listed, artists);
+            match listed_ids.iter().position(|listed_id| *listed_id == id) {
+                Some(position) => {
+                    listed_ids.remove(position);
+                    println!("{name}: no longer {}", listed.name());
+                }
+                None => println!("{name}: not {}", listed.name()),
+            }
+        }
+    }
+
+    if dry_run {
+        println!("Dry run, not saving the lists");
+        return Ok(());
+    }
+
+    save_lists(&db_path, &lists)
+}
+
+fn print_entries(listed: Listed, entries: &Entries, ratings: &HashMap<String, TimeRating>) {
+    for (kind, ids, name) in [
+        (
+            "tracks",
+            &entries.tracks,
+            track_name as fn(&str, &_) -> String,
+        ),
+        ("artists", &entries.artists, artist_name),
+    ] {
+        println!(
+            "{} {kind}:",
+            match listed {
+                Listed::Pinned => "Pinned",
+                Listed::Banned => "Banned",
+            }
+        );
+        if ids.is_empty() {
+            println!("  none");
+        }
+        for id in ids {
+            println!("  {}", name(id, ratings));
+        }
+    }
+}
+
+/// Name and artists of the track from the database, or its id if the metadata is missing
+fn track_name(track_id: &str, ratings: &HashMap<String, TimeRating>) -> String {
+    match ratings
+        .get(track_id)
+        .and_then(|time_rating| time_rating.metadata.as_ref())
+    {
+        Some(metadata) => format!(
+            "{} - {} ({track_id})",
+            metadata.name,
+            metadata.artists.join(", ")
+        ),
+        None => track_id.to_string(),
+    }
+}
+
+/// Name of the artist from the metadata of any song of theirs in the database, or its id
+fn artist_name(artist_id: &str, ratings: &HashMap<String, TimeRating>) -> String {
+    ratings
+        .values()
+        .filter_map(|time_rating| time_rating.metadata.as_ref())
+        .find_map(|metadata| {
+            metadata
+                .artist_ids
+                .iter()
+                .position(|id| id == artist_id)
+                .and_then(|position| metadata.artists.get(position))
+        })
+        .map_or_else(
+            || artist_id.to_string(),
+            |name| format!("{name} ({artist_id})"),
+        )
+}
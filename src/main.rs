@@ -1,47 +1,260 @@
-use chrono::{DateTime, Utc};
-use clap::{arg, value_parser, ArgAction, Command};
+use chrono::Utc;
+use clap::{arg, error::ErrorKind, value_parser, Arg, ArgAction, ArgGroup, ArgMatches, Command};
 use clipboard::{ClipboardContext, ClipboardProvider};
-use futures::future::join_all;
+use futures::{
+    future::{join_all, ready},
+    StreamExt, TryStreamExt,
+};
 use rspotify::{
-    model::{PlayableItem, PlaylistId, SavedTrack, TrackId, UserId},
+    model::{PlayableItem, SavedTrack, TimeRange, TrackId},
     prelude::*,
-    scopes, AuthCodeSpotify, Credentials, OAuth,
+    scopes, AuthCodeSpotify, ClientError, Credentials, OAuth,
 };
-use serde::{Deserialize, Serialize};
 use std::{
+    cmp::Reverse,
     collections::HashMap,
     error::Error,
-    fs::{create_dir_all, File},
-    io::{self, BufReader, Read, Write},
-    path::Path,
-    time::Duration,
+    fs::File,
+    io::{self, Write},
+};
+use tokio::spawn;
+
+mod compare;
+mod cover;
+mod db;
+mod diversity;
+mod journal;
+mod lists;
+mod managed;
+mod playlist;
+mod queue;
+mod range;
+mod rate;
+mod refs;
+mod requests;
+mod review;
+mod sampling;
+#[cfg(test)]
+mod test_util;
+mod weights;
+
+use cover::SpotifyCoverClient;
+use db::{
+    load_hashmap, load_or_create_hashmap, make_readable, parse_rating, TimeRating, TrackMetadata,
+    DEFAULT_RATING,
 };
-use tokio::{spawn, time::sleep};
+use diversity::DiversityRules;
+use journal::save_with_journal;
+use lists::{load_lists, Listed};
+use managed::{load_managed, print_managed, save_managed};
+use playlist::{PlaylistDetails, PlaylistOptions};
+use range::DateRange;
+use requests::RetryPolicy;
+use weights::{TimeComponents, WeightConfig, WeightRange};
+
+const DEFAULT_RATING_DB_PATH: &str = "spotility/ratings.json";
 
-#[derive(Serialize, Deserialize, Debug)]
-struct TimeRating {
-    added_at: DateTime<Utc>,
-    rating: f32,
+/// Arguments configuring how weights are created, shared by all commands using weights
+fn weighting_args() -> Vec<Arg> {
+    vec![
+        arg!(--"by-elo" "Orders the songs by their comparison score instead of their rating").action(ArgAction::SetTrue).id("BY_ELO"),
+        arg!(--strategy <STRATEGY> "How ratings are turned into weights").value_parser(weights::STRATEGY_NAMES).default_value("linear-rank").id("STRATEGY"),
+        arg!(--"min-weight" <MIN> "Weight of the worst song, only approached by the exponential strategy").value_parser(value_parser!(f64)).default_value("0").id("MIN_WEIGHT"),
+        arg!(--"max-weight" <MAX> "Weight of the best song").value_parser(value_parser!(f64)).default_value("10").id("MAX_WEIGHT"),
+        arg!(--base <BASE> "Factor the weight falls by per rating step (exponential strategy)").value_parser(weights::parse_base).default_value("2").id("BASE"),
+        arg!(--"recency-half-life" <DAYS> "Boosts newly added songs, halving the boost every DAYS").value_parser(weights::parse_half_life).id("RECENCY_HALF_LIFE"),
+        arg!(--"recency-boost" <FACTOR> "Boost of a song added just now (1 doubles its weight)").value_parser(weights::parse_boost).default_value("1").id("RECENCY_BOOST"),
+        arg!(--"staleness-half-life" <DAYS> "Fades songs that were not rated in a while, halving the difference to the floor every DAYS").value_parser(weights::parse_half_life).id("STALENESS_HALF_LIFE"),
+        arg!(--"staleness-floor" <FACTOR> "Factor stale songs fade towards").value_parser(weights::parse_floor).default_value("0.5").id("STALENESS_FLOOR"),
+        arg!(--context <CONTEXT> "Uses the ratings of the named context, falling back to the default rating").id("CONTEXT"),
+        arg!(--"max-artist-share" <FRACTION> "Maximum share of the total weight of a single artist, e.g. 0.1").value_parser(weights::parse_share).id("MAX_ARTIST_SHARE"),
+    ]
+    .into_iter()
+    // allow the arguments after subcommands like 'weights simulate'
+    .map(|arg| arg.global(true))
+    .collect()
 }
 
-impl TimeRating {
-    fn new(added_at: DateTime<Utc>, rating: f32) -> Self {
-        Self { added_at, rating }
+/// Reads the arguments of `weighting_args`, exits if the weight range is invalid
+fn weight_config(sub_matches: &ArgMatches) -> WeightConfig {
+    let min_weight = *sub_matches
+        .get_one::<f64>("MIN_WEIGHT")
+        .expect("min-weight has default value");
+    let max_weight = *sub_matches
+        .get_one::<f64>("MAX_WEIGHT")
+        .expect("max-weight has default value");
+    if min_weight < 0. || max_weight < 0. {
+        cli()
+            .error(
+                ErrorKind::ValueValidation,
+                "--min-weight and --max-weight can't be negative",
+            )
+            .exit();
+    }
+    if min_weight > max_weight {
+        cli()
+            .error(
+                ErrorKind::ArgumentConflict,
+                format!("--min-weight ({min_weight}) is larger than --max-weight ({max_weight})"),
+            )
+            .exit();
+    }
+
+    WeightConfig {
+        strategy: sub_matches
+            .get_one::<String>("STRATEGY")
+            .expect("strategy has default value")
+            .clone(),
+        range: WeightRange {
+            min: min_weight,
+            max: max_weight,
+        },
+        base: *sub_matches
+            .get_one::<f64>("BASE")
+            .expect("base has default value"),
+        time_components: TimeComponents {
+            recency_half_life: sub_matches.get_one::<f64>("RECENCY_HALF_LIFE").copied(),
+            recency_boost: *sub_matches
+                .get_one::<f64>("RECENCY_BOOST")
+                .expect("recency-boost has default value"),
+            staleness_half_life: sub_matches.get_one::<f64>("STALENESS_HALF_LIFE").copied(),
+            staleness_floor: *sub_matches
+                .get_one::<f64>("STALENESS_FLOOR")
+                .expect("staleness-floor has default value"),
+        },
+        by_elo: sub_matches.get_flag("BY_ELO"),
+        context: sub_matches.get_one::<String>("CONTEXT").cloned(),
+        max_artist_share: sub_matches.get_one::<f64>("MAX_ARTIST_SHARE").copied(),
     }
 }
 
-const DEFAULT_RATING_DB_PATH: &str = "spotility/ratings.json";
+/// Arguments constraining the order of generated playlists and queues
+fn diversity_args() -> [Arg; 2] {
+    [
+        arg!(--"artist-spacing" <SONGS> "Amount of songs before an artist may repeat")
+            .value_parser(value_parser!(usize))
+            .id("ARTIST_SPACING"),
+        arg!(--"max-per-album" <SONGS> "Maximum amount of songs of the same album")
+            .value_parser(value_parser!(usize))
+            .id("MAX_PER_ALBUM"),
+    ]
+}
+
+/// Arguments filtering songs by when they were added
+fn date_range_args() -> [Arg; 2] {
+    [
+        arg!(--since <TIME> "Only songs added since the date (2024-06-21) or duration ago (30d, 2w, 6mo, 1y)")
+            .value_parser(range::parse_since)
+            .id("SINCE"),
+        arg!(--until <TIME> "Only songs added until the date (inclusive) or duration ago")
+            .value_parser(range::parse_until)
+            .id("UNTIL"),
+    ]
+}
+
+/// Reads the arguments of `date_range_args`
+fn date_range(sub_matches: &ArgMatches) -> DateRange {
+    DateRange {
+        since: sub_matches.get_one("SINCE").copied(),
+        until: sub_matches.get_one("UNTIL").copied(),
+    }
+}
+
+/// Arguments of generated playlists
+fn playlist_args() -> [Arg; 5] {
+    [
+        arg!(--force "Overwrites the playlist even if it wasn't created by spotility")
+            .action(ArgAction::SetTrue)
+            .id("FORCE"),
+        arg!(--public "Makes the playlist public")
+            .action(ArgAction::SetTrue)
+            .id("PUBLIC"),
+        arg!(--collaborative "Makes the playlist collaborative (and private)")
+            .action(ArgAction::SetTrue)
+            .id("COLLABORATIVE")
+            .conflicts_with("PUBLIC"),
+        arg!(--description <TEMPLATE> "Description of the playlist, with the placeholders n (amount of songs), date, avg (average rating) and name in braces, e.g. 'As of {date}, avg rating {avg}'")
+            .value_parser(playlist::parse_description)
+            .id("DESCRIPTION"),
+        arg!(--cover "Generates the cover from the album arts of the first songs")
+            .action(ArgAction::SetTrue)
+            .id("COVER"),
+    ]
+}
+
+/// Reads the arguments of `playlist_args`, filling the description with the songs
+fn playlist_options(
+    sub_matches: &ArgMatches,
+    name: String,
+    songs: usize,
+    average_rating: Option<f32>,
+    dry_run: bool,
+) -> PlaylistOptions {
+    let collaborative = sub_matches.get_flag("COLLABORATIVE");
+    PlaylistOptions {
+        details: PlaylistDetails {
+            // collaborative playlists have to be private
+            public: if collaborative {
+                Some(false)
+            } else {
+                sub_matches.get_flag("PUBLIC").then_some(true)
+            },
+            collaborative: collaborative.then_some(true),
+            description: sub_matches
+                .get_one::<String>("DESCRIPTION")
+                .map(|template| {
+                    playlist::render_description(template, songs, average_rating, &name)
+                }),
+        },
+        name,
+        cover: sub_matches.get_flag("COVER"),
+        force: sub_matches.get_flag("FORCE"),
+        dry_run,
+    }
+}
+
+/// Average rating of the songs rated in the context, `None` if there are none
+fn average_rating<'a>(
+    time_ratings: impl IntoIterator<Item = &'a TimeRating>,
+    context: Option<&str>,
+) -> Option<f32> {
+    let ratings: Vec<f32> = time_ratings
+        .into_iter()
+        .filter(|time_rating| !time_rating.is_unrated_in(context))
+        .map(|time_rating| time_rating.rating_in(context))
+        .collect();
+    (!ratings.is_empty()).then(|| ratings.iter().sum::<f32>() / ratings.len() as f32)
+}
+
+/// Reads the arguments of `diversity_args`
+fn diversity_rules(sub_matches: &ArgMatches) -> DiversityRules {
+    DiversityRules {
+        artist_spacing: sub_matches.get_one::<usize>("ARTIST_SPACING").copied(),
+        max_per_album: sub_matches.get_one::<usize>("MAX_PER_ALBUM").copied(),
+    }
+}
 fn cli() -> Command {
     Command::new("spotility")
         .about("A CLI for managing your 'Liked Songs'")
         .subcommand_required(true)
         .arg_required_else_help(true)
+        .arg(arg!(--"dry-run" "Prints the planned changes to Spotify and the database, without making them").action(ArgAction::SetTrue).id("DRY_RUN").global(true))
+        .arg(arg!(--retries <RETRIES> "Retries of a failed request to Spotify").value_parser(value_parser!(u32)).default_value("5").id("RETRIES").global(true))
+        .arg(arg!(--"retry-budget" <RETRIES> "Retries of all requests to Spotify together").value_parser(value_parser!(u32)).default_value("50").id("RETRY_BUDGET").global(true))
+        .arg(arg!(--"max-concurrency" <REQUESTS> "Requests to Spotify running at the same time").value_parser(value_parser!(usize)).default_value("4").id("MAX_CONCURRENCY").global(true))
         .subcommand(
             Command::new("top")
                 .about("Extracts the newest 'Liked Songs' into a new Playlist")
+                .long_about("Extracts the newest (or best rated, highest weighted, most played) 'Liked Songs' into a new Playlist, without banned songs and with all pinned songs")
                 .arg(arg!(<AMOUNT> "Amount of songs to extract").value_parser(value_parser!(u32))).arg_required_else_help(true)
                 .arg(arg!(<USERNAME> "Spotify API username").long("username").env("SPOTIFY_API_USERNAME")).arg_required_else_help(true)
                 .arg(arg!(--name <NAME> "Name of the playlist").id("NAME"))
+                .args(playlist_args())
+                .arg(arg!(--by <ORDER> "Which songs to extract: the newest, the best rated, the highest weighted or the most played (as ranked by Spotify)").value_parser(["recency", "rating", "weight", "plays"]).default_value("recency").id("BY"))
+                .arg(arg!([DB_PATH] "The path of the rating database").long("db_path").default_value(DEFAULT_RATING_DB_PATH))
+                .args(weighting_args())
+                .args(diversity_args())
+                .args(date_range_args())
                 // spotify api authentification
                 .arg(arg!(<ID> "Spotify API authentification ID").long("id").env("SPOTIFY_API_ID"))
                 .arg(arg!(<SECRET> "Spotify API authentification secret").long("secret").env("SPOTIFY_API_SECRET"))
@@ -49,18 +262,66 @@ fn cli() -> Command {
         .subcommand(
             Command::new("rate")
                 .about("Rates the currently playing song (For use with the weights command)")
-                .arg(arg!(<RATING> "Rating to apply")).arg_required_else_help(true)
+                .arg(arg!([RATING] "Rating to apply").required_unless_present("BATCH")).arg_required_else_help(true)
                 .arg(arg!([DB_PATH] "The path of the rating database").long("db_path").default_value(DEFAULT_RATING_DB_PATH))
                 // spotify api authentification
-                .arg(arg!(<ID> "Spotify API authentification ID").long("id").env("SPOTIFY_API_ID"))
-                .arg(arg!(<SECRET> "Spotify API authentification secret").long("secret").env("SPOTIFY_API_SECRET"))
+                .arg(arg!([ID] "Spotify API authentification ID").long("id").env("SPOTIFY_API_ID").required_unless_present("BATCH"))
+                .arg(arg!([SECRET] "Spotify API authentification secret").long("secret").env("SPOTIFY_API_SECRET").required_unless_present("BATCH"))
                 .arg(arg!(--ask "Asks for confirmation for the right song").action(ArgAction::SetTrue).id("ASK"))
+                .arg(arg!(--context <CONTEXT> "Rates in the named context, like 'workout', instead of the default one").id("CONTEXT"))
+                // batch rating
+                .arg(arg!(--batch <FILE> "Rates songs from a file ('-' for stdin) with lines of '<track-ref> <rating>'").id("BATCH").conflicts_with_all(["RATING", "ASK"]))
+                // collection rating
+                .arg(arg!(--playlist <PLAYLIST> "Rates every song of the playlist (ID, URI or URL)").id("PLAYLIST"))
+                .arg(arg!(--album <ALBUM> "Rates every song of the album (ID, URI or URL)").id("ALBUM"))
+                .group(ArgGroup::new("COLLECTION").args(["PLAYLIST", "ALBUM"]).conflicts_with_all(["BATCH", "ASK"]))
+                .arg(arg!(--"only-unrated" "Only rates songs of the collection that are unrated").action(ArgAction::SetTrue).id("ONLY_UNRATED").requires("COLLECTION"))
         )
         .subcommand(
             Command::new("weights")
                 .about("Updates/Creates the user playlist 'Liked Songs' and generates weights for use with weighting spotify plugin")
+                .arg(arg!([DB_PATH] "The path of the rating database").long("db_path").default_value(DEFAULT_RATING_DB_PATH).global(true))
+                .arg(arg!(--"output-file" <PATH> "Writes the weights to the file instead of the clipboard").id("PATH"))
+                .arg(arg!(--stdout "Prints the weights to stdout instead of the clipboard").action(ArgAction::SetTrue).id("STDOUT").conflicts_with("PATH"))
+                .arg(arg!(--format <FORMAT> "Output format of the weights").value_parser(weights::FORMAT_NAMES).default_value("plugin").id("FORMAT"))
+                .args(weighting_args())
+                .args(date_range_args().map(|arg| arg.global(true)))
+                .arg(arg!(--explain [TRACK] "Prints the formula the weights are built with and how the weight of the track (ID, URI or URL) came to be, instead of the weights").num_args(0..=1).id("EXPLAIN"))
+                .subcommand(
+                    Command::new("simulate")
+                        .about("Simulates a weighted shuffle with the weights and reports how often songs get played")
+                        .arg(arg!(--plays <PLAYS> "Amount of simulated plays").value_parser(value_parser!(u32).range(1..)).default_value("1000").id("PLAYS"))
+                        .arg(arg!(--seed <SEED> "Seed of the random sampler, random if not given").value_parser(value_parser!(u64)).id("SEED"))
+                )
+        )
+        .subcommand(
+            Command::new("shuffle")
+                .about("Samples songs of the rating database by weight into a playlist")
+                .arg(arg!(<AMOUNT> "Amount of songs to sample").value_parser(value_parser!(u32))).arg_required_else_help(true)
+                .arg(arg!(<USERNAME> "Spotify API username").long("username").env("SPOTIFY_API_USERNAME"))
+                .arg(arg!(--name <NAME> "Name of the playlist").default_value("Weighted Shuffle").id("NAME"))
+                .args(playlist_args())
+                .arg(arg!(--seed <SEED> "Seed of the random sampler, random if not given").value_parser(value_parser!(u64)).id("SEED"))
+                .arg(arg!([DB_PATH] "The path of the rating database").long("db_path").default_value(DEFAULT_RATING_DB_PATH))
+                .args(weighting_args())
+                .args(diversity_args())
+                // spotify api authentification
+                .arg(arg!(<ID> "Spotify API authentification ID").long("id").env("SPOTIFY_API_ID"))
+                .arg(arg!(<SECRET> "Spotify API authentification secret").long("secret").env("SPOTIFY_API_SECRET"))
+        )
+        .subcommand(
+            Command::new("queue")
+                .about("Adds songs of the rating database, sampled by weight, to the playback queue")
+                .arg(arg!(<AMOUNT> "Amount of songs to queue").value_parser(value_parser!(u32))).arg_required_else_help(true)
+                .arg(arg!(--device <DEVICE> "Name or ID of the device, the active one if not given").id("DEVICE"))
+                .arg(arg!(--"repeat-window" <SONGS> "Amount of songs before a song may repeat").value_parser(value_parser!(usize)).default_value("50").id("REPEAT_WINDOW"))
+                .arg(arg!(--seed <SEED> "Seed of the random sampler, random if not given").value_parser(value_parser!(u64)).id("SEED"))
                 .arg(arg!([DB_PATH] "The path of the rating database").long("db_path").default_value(DEFAULT_RATING_DB_PATH))
-                .arg(arg!(--"output-file" <PATH> "Print the weighths to the stdOut").id("PATH"))
+                .args(weighting_args())
+                .args(diversity_args())
+                // spotify api authentification
+                .arg(arg!(<ID> "Spotify API authentification ID").long("id").env("SPOTIFY_API_ID"))
+                .arg(arg!(<SECRET> "Spotify API authentification secret").long("secret").env("SPOTIFY_API_SECRET"))
         )
         .subcommand(
             Command::new("update-db")
@@ -71,10 +332,76 @@ fn cli() -> Command {
                 .arg(arg!(<ID> "Spotify API authentification ID").long("id").env("SPOTIFY_API_ID"))
                 .arg(arg!(<SECRET> "Spotify API authentification secret").long("secret").env("SPOTIFY_API_SECRET"))
         )
+        .subcommand(
+            Command::new("review")
+                .about("Interactively rates all unrated songs of the rating database")
+                .arg(arg!(--order <ORDER> "Order in which the songs are reviewed").value_parser(["newest", "oldest"]).default_value("newest").id("ORDER"))
+                .arg(arg!(--play "Starts playback of each song on the active device").action(ArgAction::SetTrue).id("PLAY"))
+                .arg(arg!([DB_PATH] "The path of the rating database").long("db_path").default_value(DEFAULT_RATING_DB_PATH))
+                // spotify api authentification
+                .arg(arg!(<ID> "Spotify API authentification ID").long("id").env("SPOTIFY_API_ID"))
+                .arg(arg!(<SECRET> "Spotify API authentification secret").long("secret").env("SPOTIFY_API_SECRET"))
+        )
+        .subcommand(
+            Command::new("compare")
+                .about("Ranks songs of the rating database by comparing them in pairs")
+                .arg(arg!(--rounds <ROUNDS> "Amount of comparisons, unlimited if not given").value_parser(value_parser!(usize)).id("ROUNDS"))
+                .arg(arg!(--"apply-ratings" "Derives the ratings of all compared songs from their score").action(ArgAction::SetTrue).id("APPLY_RATINGS").conflicts_with("ROUNDS"))
+                .arg(arg!([DB_PATH] "The path of the rating database").long("db_path").default_value(DEFAULT_RATING_DB_PATH))
+                // spotify api authentification
+                .arg(arg!([ID] "Spotify API authentification ID").long("id").env("SPOTIFY_API_ID").required_unless_present("APPLY_RATINGS"))
+                .arg(arg!([SECRET] "Spotify API authentification secret").long("secret").env("SPOTIFY_API_SECRET").required_unless_present("APPLY_RATINGS"))
+        )
+        .subcommand(
+            Command::new("list")
+                .about("Shows and changes the pinned and banned tracks and artists")
+                .arg(arg!(--pinned "Songs that are always included in generated playlists, with the highest weight").action(ArgAction::SetTrue).id("PINNED"))
+                .arg(arg!(--banned "Songs that are never included in generated playlists, with a weight of 0").action(ArgAction::SetTrue).id("BANNED"))
+                .group(ArgGroup::new("LIST").args(["PINNED", "BANNED"]).required(true))
+                .arg(arg!(--add <REF> "Adds tracks to the list (ID, URI or URL)").num_args(1..).action(ArgAction::Append).id("ADD"))
+                .arg(arg!(--remove <REF> "Removes tracks from the list (ID, URI or URL)").num_args(1..).action(ArgAction::Append).id("REMOVE").conflicts_with("ADD"))
+                .arg(arg!(--artist "The added/removed references are artists instead of tracks").action(ArgAction::SetTrue).id("ARTIST"))
+                .arg(arg!([DB_PATH] "The path of the rating database").long("db_path").default_value(DEFAULT_RATING_DB_PATH))
+        )
+        .subcommand(
+            Command::new("playlists")
+                .about("Shows the playlists created by spotility")
+                .subcommand_required(true)
+                .subcommand(
+                    Command::new("managed")
+                        .about("Lists the playlists spotility created or took over with --force, which it may overwrite")
+                        .arg(arg!([DB_PATH] "The path of the rating database").long("db_path").default_value(DEFAULT_RATING_DB_PATH))
+                )
+        )
+        .subcommand(
+            Command::new("undo")
+                .about("Reverts the last changes to the rating database")
+                .arg(arg!([STEPS] "Amount of changes to revert").default_value("1").value_parser(value_parser!(usize)))
+                .arg(arg!([DB_PATH] "The path of the rating database").long("db_path").default_value(DEFAULT_RATING_DB_PATH))
+        )
+        .subcommand(
+            Command::new("redo")
+                .about("Reapplies the last reverted changes to the rating database")
+                .arg(arg!([STEPS] "Amount of changes to reapply").default_value("1").value_parser(value_parser!(usize)))
+                .arg(arg!([DB_PATH] "The path of the rating database").long("db_path").default_value(DEFAULT_RATING_DB_PATH))
+        )
 }
 #[tokio::main]
 async fn main() -> Result<(), Box<dyn Error>> {
     let matches = cli().get_matches();
+    let dry_run = matches.get_flag("DRY_RUN");
+    requests::configure(RetryPolicy {
+        retries: *matches
+            .get_one::<u32>("RETRIES")
+            .expect("retries has default value"),
+        budget: *matches
+            .get_one::<u32>("RETRY_BUDGET")
+            .expect("retry-budget has default value"),
+        max_concurrency: *matches
+            .get_one::<usize>("MAX_CONCURRENCY")
+            .expect("max-concurrency has default value"),
+        ..RetryPolicy::default()
+    });
 
     match matches.subcommand() {
         Some(("top", sub_matches)) => {
@@ -96,23 +423,163 @@ async fn main() -> Result<(), Box<dyn Error>> {
                 None => format!("Top {amount}"),
             };
 
-            // get track id's
-            let liked_songs_ids = get_liked_songs(spotify.clone(), *amount)
-                .await
-                .unwrap()
-                .into_iter()
-                .map(|saved_track| saved_track.track.id.unwrap())
-                .collect();
+            // get db path
+            let db_path = sub_matches
+                .get_one::<String>("DB_PATH")
+                .expect("db-path has default value");
 
-            // search/create playlist with correct name
-            let playlist_id = empty_playlist(spotify.clone(), username.clone(), playlist_name)
-                .await
-                .unwrap();
+            // get pinned and banned songs
+            let lists = match load_lists(db_path) {
+                Ok(lists) => lists,
+                Err(e) => {
+                    println!("Error loading lists: {e}");
+                    return Ok(());
+                }
+            };
+            let by = sub_matches
+                .get_one::<String>("BY")
+                .expect("by has default value");
+            // the database is only required when ranking by it,
+            // otherwise it's only needed for the songs of pinned artists
+            let ratings = match load_hashmap(db_path.clone()) {
+                Ok(hashmap) => hashmap,
+                Err(e) if by == "rating" || by == "weight" => {
+                    println!("Error loading database: {e}");
+                    return Ok(());
+                }
+                Err(_) => HashMap::new(),
+            };
+            let date_range = date_range(sub_matches);
+            if by == "plays" && !date_range.is_unbounded() {
+                println!("--since/--until can't be used with --by plays, Spotify's top tracks have no time they were added");
+                return Ok(());
+            }
+            // only rank the songs of the database added in the range
+            let mut ranked = ratings.clone();
+            ranked.retain(|_, time_rating| date_range.contains_known(time_rating.added_at));
+            let not_banned = |(song_id, metadata): &(String, Option<TrackMetadata>)| {
+                lists.status(song_id, metadata.as_ref()) != Some(Listed::Banned)
+            };
 
-            // replace songs in playlist
-            populate_playlist(spotify, playlist_id, liked_songs_ids)
-                .await
-                .unwrap();
+            // get track id's, without banned songs
+            let mut songs: Vec<_> = match by.as_str() {
+                "recency" => {
+                    // the range has to be paged through, the newest songs can be fetched at once
+                    let liked_songs = if date_range.is_unbounded() {
+                        get_liked_songs(spotify.clone(), *amount)
+                            .await
+                            .map_err(|e| e as Box<dyn Error>)?
+                    } else {
+                        get_liked_songs_in(&spotify, *amount, &date_range).await?
+                    };
+                    liked_songs
+                        .into_iter()
+                        .map(|saved_track| {
+                            (
+                                saved_track.track.id.clone().unwrap().id().to_string(),
+                                Some(TrackMetadata::from(&saved_track.track)),
+                            )
+                        })
+                        .filter(not_banned)
+                        .collect()
+                }
+                "plays" => requests::paginate(|limit, offset| {
+                    spotify.current_user_top_tracks_manual(
+                        Some(TimeRange::LongTerm),
+                        Some(limit),
+                        Some(offset),
+                    )
+                })
+                .take(*amount as usize)
+                .try_collect::<Vec<_>>()
+                .await?
+                .into_iter()
+                // local files have no id
+                .filter_map(|track| {
+                    let metadata = TrackMetadata::from(&track);
+                    track.id.map(|id| (id.id().to_string(), Some(metadata)))
+                })
+                .filter(not_banned)
+                .collect(),
+                "rating" => {
+                    let context = sub_matches.get_one::<String>("CONTEXT").map(String::as_str);
+                    // best rated first, newer songs first among the same rating
+                    let mut by_rating: Vec<_> = ranked.iter().collect();
+                    by_rating.sort_by_key(|(_, time_rating)| Reverse(time_rating.added_at));
+                    by_rating
+                        .sort_by(|a, b| a.1.rating_in(context).total_cmp(&b.1.rating_in(context)));
+                    by_rating
+                        .into_iter()
+                        .map(|(song_id, time_rating)| {
+                            (song_id.clone(), time_rating.metadata.clone())
+                        })
+                        .filter(not_banned)
+                        .take(*amount as usize)
+                        .collect()
+                }
+                "weight" => {
+                    let mut weighted_songs =
+                        weight_config(sub_matches).create_weights(ranked, &lists, Utc::now());
+                    weighted_songs.sort_by(|a, b| b.weight.total_cmp(&a.weight));
+                    weighted_songs
+                        .into_iter()
+                        .map(|weighted_song| {
+                            (weighted_song.song_id, weighted_song.time_rating.metadata)
+                        })
+                        .filter(not_banned)
+                        .take(*amount as usize)
+                        .collect()
+                }
+                other => unreachable!("unknown order '{other}'"), // All orders listed
+            };
+            // add the pinned songs that are not among them
+            for song_id in lists.pinned_songs(&ratings) {
+                if songs.iter().all(|(other_id, _)| *other_id != song_id) {
+                    let metadata = ratings
+                        .get(&song_id)
+                        .and_then(|time_rating| time_rating.metadata.clone());
+                    songs.push((song_id, metadata));
+                }
+            }
+            let order = diversity_rules(sub_matches).arrange(
+                &songs
+                    .iter()
+                    .map(|(_, metadata)| metadata.as_ref())
+                    .collect::<Vec<_>>(),
+            );
+            if order.len() < songs.len() {
+                println!(
+                    "Dropped {} songs over the album limit (--max-per-album), the playlist has {} songs",
+                    songs.len() - order.len(),
+                    order.len()
+                );
+            }
+            let liked_songs_ids = order
+                .into_iter()
+                .map(|index| TrackId::from_id(songs[index].0.clone()))
+                .collect::<Result<Vec<_>, _>>()?;
+            let options = playlist_options(
+                sub_matches,
+                playlist_name,
+                liked_songs_ids.len(),
+                average_rating(
+                    liked_songs_ids
+                        .iter()
+                        .filter_map(|song_id| ratings.get(song_id.id())),
+                    sub_matches.get_one::<String>("CONTEXT").map(String::as_str),
+                ),
+                dry_run,
+            );
+
+            // search/create playlist with correct name and sync its songs
+            write_playlist(
+                &spotify,
+                username.clone(),
+                liked_songs_ids,
+                db_path,
+                options,
+            )
+            .await?;
         }
         Some(("weights", sub_matches)) => {
             // get db path
@@ -121,37 +588,87 @@ async fn main() -> Result<(), Box<dyn Error>> {
                 .expect("db-path has default value");
 
             // get ratings db
-            let ratings = match load_hashmap(db_path.clone()) {
+            let mut ratings = match load_hashmap(db_path.clone()) {
                 Ok(hashmap) => hashmap,
                 Err(e) => {
                     println!("Error loading database: {e}");
                     return Ok(());
                 }
             };
+            let date_range = date_range(sub_matches);
+            ratings.retain(|_, time_rating| date_range.contains_known(time_rating.added_at));
 
-            let mut ratings_vec: Vec<_> = ratings.into_iter().collect();
-            // sort the vec by time added (unstable, because faster)
-            ratings_vec.sort_by(|a, b| b.1.added_at.cmp(&a.1.added_at));
-            ratings_vec.sort_by(|a, b| {
-                a.1.rating
-                    .partial_cmp(&b.1.rating)
-                    .expect("all elements have a (non NaN) rating")
-            });
+            // get pinned and banned songs
+            let lists = match load_lists(db_path) {
+                Ok(lists) => lists,
+                Err(e) => {
+                    println!("Error loading lists: {e}");
+                    return Ok(());
+                }
+            };
+
+            let weight_config = weight_config(sub_matches);
+
+            // simulate instead of outputting the weights
+            if let Some(("simulate", simulate_matches)) = sub_matches.subcommand() {
+                let weighted_songs = weight_config.create_weights(ratings, &lists, Utc::now());
+                return sampling::simulate(
+                    &weighted_songs,
+                    *simulate_matches
+                        .get_one::<u32>("PLAYS")
+                        .expect("plays has default value"),
+                    simulate_matches.get_one::<u64>("SEED").copied(),
+                );
+            }
 
-            let ratings_len = ratings_vec.len();
-            let step = 10.0 / ratings_len as f64;
+            // status messages would mix with the weights/explanation on stdout
+            let to_stdout = sub_matches.get_flag("STDOUT") || sub_matches.contains_id("EXPLAIN");
 
             // creating weights
-            println!("Creating weights");
-            let weights = ratings_vec
-                .into_iter()
-                .enumerate()
-                .map(|(i, (song_id, _))| {
-                    let rating = 9.0 - (step * i as f64) + 1.0;
-                    format!("{}:{:.2}", song_id, rating)
-                })
-                .collect::<Vec<String>>()
-                .join("|");
+            if !to_stdout {
+                println!("Creating weights");
+            }
+            let weighted_songs = weight_config.create_weights(ratings, &lists, Utc::now());
+
+            // explain instead of outputting the weights
+            if sub_matches.contains_id("EXPLAIN") {
+                for line in weight_config.formula(&lists) {
+                    println!("{line}");
+                }
+
+                if let Some(track) = sub_matches.get_one::<String>("EXPLAIN") {
+                    let Some(track_id) = refs::parse_track_ref(track) else {
+                        println!("Invalid track reference '{track}'");
+                        return Ok(());
+                    };
+                    match weighted_songs
+                        .iter()
+                        .find(|weighted_song| weighted_song.song_id == track_id.id())
+                    {
+                        Some(weighted_song) => {
+                            println!();
+                            for line in weights::explain(weighted_song, weighted_songs.len()) {
+                                println!("{line}");
+                            }
+                        }
+                        None => println!("Song {} not in local database", track_id.id()),
+                    }
+                }
+
+                return Ok(());
+            }
+
+            let weights = weights::format_weights(
+                &weighted_songs,
+                sub_matches
+                    .get_one::<String>("FORMAT")
+                    .expect("format has default value"),
+            )?;
+
+            if to_stdout {
+                println!("{weights}");
+                return Ok(());
+            }
 
             match sub_matches.get_one::<String>("PATH") {
                 Some(path) => {
@@ -168,7 +685,76 @@ async fn main() -> Result<(), Box<dyn Error>> {
                 }
             }
         }
-        Some(("rate", sub_matches)) => {
+        Some(("shuffle", sub_matches)) => {
+            // get db path
+            let db_path = sub_matches
+                .get_one::<String>("DB_PATH")
+                .expect("db-path has default value");
+
+            // get ratings db
+            let ratings = match load_hashmap(db_path.clone()) {
+                Ok(hashmap) => hashmap,
+                Err(e) => {
+                    println!("Error loading database: {e}");
+                    return Ok(());
+                }
+            };
+
+            // get pinned and banned songs
+            let lists = match load_lists(db_path) {
+                Ok(lists) => lists,
+                Err(e) => {
+                    println!("Error loading lists: {e}");
+                    return Ok(());
+                }
+            };
+
+            let amount = sub_matches
+                .get_one::<u32>("AMOUNT")
+                .expect("amount is required");
+            let username = sub_matches
+                .get_one::<String>("USERNAME")
+                .expect("username is required");
+            let playlist_name = sub_matches
+                .get_one::<String>("NAME")
+                .expect("name has default value");
+
+            // sample songs
+            let weighted_songs =
+                weight_config(sub_matches).create_weights(ratings, &lists, Utc::now());
+            let mut rng = sampling::rng(sub_matches.get_one::<u64>("SEED").copied());
+            // sample all songs, so songs dropped by the diversity rules get replaced
+            let samples = sampling::sample_without_replacement(
+                &weighted_songs,
+                weighted_songs.len(),
+                &mut rng,
+            );
+            let order = diversity_rules(sub_matches).arrange(
+                &samples
+                    .iter()
+                    .map(|weighted_song| weighted_song.time_rating.metadata.as_ref())
+                    .collect::<Vec<_>>(),
+            );
+            // pinned songs are kept even beyond the amount
+            let selected = lists::select(order, *amount as usize, |index| {
+                samples[index].listed == Some(Listed::Pinned)
+            });
+            let song_ids = selected
+                .iter()
+                .map(|&index| TrackId::from_id(samples[index].song_id.clone()))
+                .collect::<Result<Vec<_>, _>>()?;
+            println!("Sampled {} songs", song_ids.len());
+            let options = playlist_options(
+                sub_matches,
+                playlist_name.clone(),
+                song_ids.len(),
+                average_rating(
+                    selected.iter().map(|&index| &samples[index].time_rating),
+                    sub_matches.get_one::<String>("CONTEXT").map(String::as_str),
+                ),
+                dry_run,
+            );
+
             // api authentification
             let id = sub_matches.get_one::<String>("ID").expect("ID is required");
             let secret = sub_matches
@@ -176,31 +762,135 @@ async fn main() -> Result<(), Box<dyn Error>> {
                 .expect("SECRET is required");
             let spotify = authenticate(id, secret).await;
 
-            // get rating
-            let rating = match sub_matches
-                .get_one::<String>("RATING")
-                .expect("rating is required") as &str
-            {
-                "great" | "1" => 1.,
-                "good" | "2" => 2.,
-                "ok" | "3" => 3.,
-                "bad" | "4" => 4.,
-                other => other.parse::<f32>().unwrap(),
+            // search/create playlist with correct name and sync its songs
+            write_playlist(&spotify, username.clone(), song_ids, db_path, options).await?;
+        }
+        Some(("queue", sub_matches)) => {
+            // get db path
+            let db_path = sub_matches
+                .get_one::<String>("DB_PATH")
+                .expect("db-path has default value");
+
+            // get ratings db
+            let ratings = match load_hashmap(db_path.clone()) {
+                Ok(hashmap) => hashmap,
+                Err(e) => {
+                    println!("Error loading database: {e}");
+                    return Ok(());
+                }
+            };
+
+            // get pinned and banned songs
+            let lists = match load_lists(db_path) {
+                Ok(lists) => lists,
+                Err(e) => {
+                    println!("Error loading lists: {e}");
+                    return Ok(());
+                }
             };
+
+            // api authentification
+            let id = sub_matches.get_one::<String>("ID").expect("ID is required");
+            let secret = sub_matches
+                .get_one::<String>("SECRET")
+                .expect("SECRET is required");
+            let spotify = authenticate(id, secret).await;
+
+            let weighted_songs =
+                weight_config(sub_matches).create_weights(ratings, &lists, Utc::now());
+            let samples = sampling::sample_with_window(
+                &weighted_songs,
+                *sub_matches
+                    .get_one::<u32>("AMOUNT")
+                    .expect("amount is required") as usize,
+                *sub_matches
+                    .get_one::<usize>("REPEAT_WINDOW")
+                    .expect("repeat-window has default value"),
+                &queue::current_queue(&spotify).await?,
+                &diversity_rules(sub_matches),
+                &mut sampling::rng(sub_matches.get_one::<u64>("SEED").copied()),
+            );
+            queue::queue(
+                &spotify,
+                &samples,
+                sub_matches.get_one::<String>("DEVICE").map(String::as_str),
+                dry_run,
+            )
+            .await?;
+        }
+        Some(("rate", sub_matches)) => {
             // get db path
             let db_path = sub_matches
                 .get_one::<String>("DB_PATH")
                 .expect("db-path has default value");
+            let context = sub_matches.get_one::<String>("CONTEXT").map(String::as_str);
 
-            // get currently playing song
-            let currently_playing_song = match spotify.current_user_playing_item().await? {
-                Some(currently_playing_context) => currently_playing_context.item.unwrap(),
+            // rate songs from file/stdin
+            if let Some(batch_path) = sub_matches.get_one::<String>("BATCH") {
+                return rate::rate_batch(db_path.clone(), batch_path, context, dry_run);
+            }
+
+            // api authentification
+            let id = sub_matches.get_one::<String>("ID").expect("ID is required");
+            let secret = sub_matches
+                .get_one::<String>("SECRET")
+                .expect("SECRET is required");
+            let spotify = authenticate(id, secret).await;
+
+            // get rating
+            let rating = match parse_rating(
+                sub_matches
+                    .get_one::<String>("RATING")
+                    .expect("rating is required"),
+            ) {
+                Some(rating) => rating,
                 None => {
-                    println!("No currently playing song");
+                    println!("Invalid rating");
                     return Ok(());
                 }
             };
 
+            // rate every song of a playlist/album
+            if sub_matches.contains_id("COLLECTION") {
+                let (collection_name, tracks) =
+                    if let Some(playlist) = sub_matches.get_one::<String>("PLAYLIST") {
+                        let Some(playlist_id) = refs::parse_playlist_ref(playlist) else {
+                            println!("Invalid playlist reference '{playlist}'");
+                            return Ok(());
+                        };
+                        rate::get_playlist_tracks(&spotify, playlist_id).await?
+                    } else {
+                        let album = sub_matches
+                            .get_one::<String>("ALBUM")
+                            .expect("collection is either playlist or album");
+                        let Some(album_id) = refs::parse_album_ref(album) else {
+                            println!("Invalid album reference '{album}'");
+                            return Ok(());
+                        };
+                        rate::get_album_tracks(&spotify, album_id).await?
+                    };
+
+                return rate::rate_collection(
+                    db_path.clone(),
+                    &collection_name,
+                    tracks,
+                    rating,
+                    context,
+                    sub_matches.get_flag("ONLY_UNRATED"),
+                    dry_run,
+                );
+            }
+
+            // get currently playing song
+            let currently_playing_song =
+                match requests::request(|| spotify.current_user_playing_item()).await? {
+                    Some(currently_playing_context) => currently_playing_context.item.unwrap(),
+                    None => {
+                        println!("No currently playing song");
+                        return Ok(());
+                    }
+                };
+
             match sub_matches.get_flag("ASK") {
                 false => {
                     // print currently rating song
@@ -247,7 +937,7 @@ async fn main() -> Result<(), Box<dyn Error>> {
                 make_readable(
                     match ratings.get(currently_playing_song.id().unwrap().id()) {
                         // Print rating if song is found
-                        Some(time_rating) => time_rating.rating,
+                        Some(time_rating) => time_rating.rating_in(context),
                         // Print error message and exit if song is not found
                         None => {
                             println!("Error fetching song from local database.");
@@ -259,12 +949,13 @@ async fn main() -> Result<(), Box<dyn Error>> {
             );
 
             // apply change
+            let before = ratings.clone();
             ratings
                 .get_mut(currently_playing_song.id().unwrap().id())
                 .unwrap()
-                .rating = rating;
+                .rate_in(context, rating);
 
-            save_hashmap(db_path.clone(), &ratings)?;
+            save_with_journal(db_path.clone(), "rate", &before, &ratings, dry_run)?;
         }
         Some(("update-db", sub_matches)) => {
             // api authentification
@@ -284,7 +975,9 @@ async fn main() -> Result<(), Box<dyn Error>> {
                 .expect("db_path is required");
 
             // get liked songs up until the limit
-            let liked_songs_to_limit = get_liked_songs(spotify, *limit).await.unwrap();
+            let liked_songs_to_limit = get_liked_songs(spotify, *limit)
+                .await
+                .map_err(|e| e as Box<dyn Error>)?;
 
             // get ratings db
             let mut ratings = load_or_create_hashmap(db_path.clone())?;
@@ -293,65 +986,127 @@ async fn main() -> Result<(), Box<dyn Error>> {
                 println!("No local database, creating new one");
             }
 
+            let before = ratings.clone();
             for liked_song in liked_songs_to_limit {
-                let _ = ratings
-                    .entry(liked_song.track.id.unwrap().id().to_string())
-                    .or_insert(TimeRating::new(liked_song.added_at, 3.));
+                let time_rating = ratings
+                    .entry(liked_song.track.id.clone().unwrap().id().to_string())
+                    .or_insert(TimeRating::new(Some(liked_song.added_at), DEFAULT_RATING));
+                // songs rated from a collection get their time once they are liked
+                time_rating.added_at.get_or_insert(liked_song.added_at);
+                time_rating.metadata = Some(TrackMetadata::from(&liked_song.track));
             }
 
-            save_hashmap(db_path.clone(), &ratings)?;
+            save_with_journal(db_path.clone(), "update-db", &before, &ratings, dry_run)?;
         }
-        _ => unreachable!(), // All subcommands listed
-    };
+        Some(("review", sub_matches)) => {
+            // api authentification
+            let id = sub_matches.get_one::<String>("ID").expect("ID is required");
+            let secret = sub_matches
+                .get_one::<String>("SECRET")
+                .expect("SECRET is required");
+            let spotify = authenticate(id, secret).await;
 
-    Ok(())
-}
+            let newest_first = sub_matches
+                .get_one::<String>("ORDER")
+                .expect("order has default value")
+                == "newest";
+            let db_path = sub_matches
+                .get_one::<String>("DB_PATH")
+                .expect("db-path has default value");
 
-fn make_readable(input: f32) -> String {
-    match input {
-        1.0 => "great".to_string(),
-        2.0 => "good".to_string(),
-        3.0 => "ok".to_string(),
-        4.0 => "bad".to_string(),
-        other => other.to_string(),
-    }
-}
+            review::review(
+                spotify,
+                db_path.clone(),
+                newest_first,
+                sub_matches.get_flag("PLAY"),
+                dry_run,
+            )
+            .await?;
+        }
+        Some(("compare", sub_matches)) => {
+            let db_path = sub_matches
+                .get_one::<String>("DB_PATH")
+                .expect("db-path has default value");
 
-fn load_or_create_hashmap(
-    file_path: String,
-) -> Result<HashMap<String, TimeRating>, Box<dyn Error>> {
-    load_hashmap(file_path).map_or(Ok(HashMap::new()), Ok)
-}
+            if sub_matches.get_flag("APPLY_RATINGS") {
+                return compare::apply_ratings(db_path.clone(), dry_run);
+            }
+
+            // api authentification
+            let id = sub_matches.get_one::<String>("ID").expect("ID is required");
+            let secret = sub_matches
+                .get_one::<String>("SECRET")
+                .expect("SECRET is required");
+            let spotify = authenticate(id, secret).await;
 
-fn load_hashmap(file_path: String) -> Result<HashMap<String, TimeRating>, Box<dyn Error>> {
-    match File::open(file_path) {
-        Ok(file) => {
-            // If the file exists, attempt to read from it
-            let mut file_contents = String::new();
-            let mut buf_reader = BufReader::new(file);
-            buf_reader.read_to_string(&mut file_contents)?;
-            // Deserialize JSON to HashMap
-            Ok(serde_json::from_str(&file_contents)?)
+            compare::compare(
+                spotify,
+                db_path.clone(),
+                sub_matches.get_one::<usize>("ROUNDS").copied(),
+                dry_run,
+            )
+            .await?;
         }
-        // If the file doesnt exist, create a new HashMap
-        Err(e) => Err(Box::new(e)),
-    }
-}
+        Some(("list", sub_matches)) => {
+            let db_path = sub_matches
+                .get_one::<String>("DB_PATH")
+                .expect("db-path has default value");
+            let references = |id| {
+                sub_matches
+                    .get_many::<String>(id)
+                    .unwrap_or_default()
+                    .cloned()
+                    .collect::<Vec<_>>()
+            };
 
-fn save_hashmap(
-    file_path: String,
-    hashmap: &HashMap<String, TimeRating>,
-) -> Result<(), Box<dyn Error>> {
-    let serialized_hashmap = serde_json::to_string(hashmap)?;
+            lists::list(
+                db_path.clone(),
+                if sub_matches.get_flag("PINNED") {
+                    Listed::Pinned
+                } else {
+                    Listed::Banned
+                },
+                &references("ADD"),
+                &references("REMOVE"),
+                sub_matches.get_flag("ARTIST"),
+                dry_run,
+            )?;
+        }
+        Some(("playlists", sub_matches)) => match sub_matches.subcommand() {
+            Some(("managed", sub_matches)) => {
+                let db_path = sub_matches
+                    .get_one::<String>("DB_PATH")
+                    .expect("db-path has default value");
 
-    // Extract the parent directory from the provided file path
-    if let Some(parent) = Path::new(&file_path).parent() {
-        create_dir_all(parent)?; // Create the directory structure if it does not exist
-    }
+                match load_managed(db_path) {
+                    Ok(managed) => print_managed(&managed),
+                    Err(e) => println!("Error loading managed playlists: {e}"),
+                }
+            }
+            _ => unreachable!(), // All subcommands listed
+        },
+        Some(("undo", sub_matches)) => {
+            let steps = sub_matches
+                .get_one::<usize>("STEPS")
+                .expect("steps has default value");
+            let db_path = sub_matches
+                .get_one::<String>("DB_PATH")
+                .expect("db-path has default value");
 
-    let mut file = File::create(file_path)?;
+            journal::undo(db_path.clone(), *steps, dry_run)?;
+        }
+        Some(("redo", sub_matches)) => {
+            let steps = sub_matches
+                .get_one::<usize>("STEPS")
+                .expect("steps has default value");
+            let db_path = sub_matches
+                .get_one::<String>("DB_PATH")
+                .expect("db-path has default value");
 
-    file.write_all(serialized_hashmap.as_bytes())?;
+            journal::redo(db_path.clone(), *steps, dry_run)?;
+        }
+        _ => unreachable!(), // All subcommands listed
+    };
 
     Ok(())
 }
@@ -361,7 +1116,7 @@ async fn authenticate(id: &str, secret: &str) -> AuthCodeSpotify {
 
     let oauth = OAuth {
         redirect_uri: "http://localhost:8888/callback/".to_string(),
-        scopes: scopes!("playlist-modify-public playlist-modify-private user-library-read playlist-read-private user-read-currently-playing"),
+        scopes: scopes!("playlist-modify-public playlist-modify-private user-library-read playlist-read-private user-read-currently-playing user-modify-playback-state user-read-playback-state user-top-read ugc-image-upload"),
         ..Default::default()
     };
 
@@ -373,6 +1128,75 @@ async fn authenticate(id: &str, secret: &str) -> AuthCodeSpotify {
     spotify
 }
 
+/// Writes the songs into the playlist, records it as managed by spotility and sets its cover
+async fn write_playlist(
+    spotify: &AuthCodeSpotify,
+    username: String,
+    song_ids: Vec<TrackId<'static>>,
+    db_path: &str,
+    options: PlaylistOptions,
+) -> Result<(), Box<dyn Error>> {
+    let mut managed = match load_managed(db_path) {
+        Ok(managed) => managed,
+        Err(e) => {
+            println!("Error loading managed playlists: {e}");
+            return Ok(());
+        }
+    };
+
+    let result =
+        playlist::write_playlist(spotify, username, song_ids.clone(), &mut managed, &options).await;
+    // saved even if the sync failed, as the playlist may have been created
+    if !options.dry_run {
+        save_managed(db_path, &managed)?;
+    }
+
+    let playlist_id = result.map_err(|e| e as Box<dyn Error>)?;
+    if let Some(playlist_id) = playlist_id {
+        if options.cover {
+            // the playlist is written, so a failed cover isn't fatal
+            if let Err(e) = cover::set_cover(
+                &SpotifyCoverClient::new(spotify),
+                &playlist_id,
+                &song_ids,
+                options.dry_run,
+            )
+            .await
+            {
+                println!("Error setting cover: {e}");
+            }
+        }
+    }
+
+    Ok(())
+}
+
+/// Gets the newest `amount` liked songs added in the range,
+/// paging through the songs until the range is left
+async fn get_liked_songs_in(
+    spotify: &AuthCodeSpotify,
+    amount: u32,
+    date_range: &DateRange,
+) -> Result<Vec<SavedTrack>, ClientError> {
+    // liked songs are sorted newest first
+    requests::paginate(|limit, offset| {
+        spotify.current_user_saved_tracks_manual(None, Some(limit), Some(offset))
+    })
+    .try_skip_while(|saved_track| {
+        ready(Ok(date_range
+            .until
+            .is_some_and(|until| saved_track.added_at >= until)))
+    })
+    .try_take_while(|saved_track| {
+        ready(Ok(date_range
+            .since
+            .is_none_or(|since| saved_track.added_at >= since)))
+    })
+    .take(amount as usize)
+    .try_collect()
+    .await
+}
+
 async fn get_liked_songs(
     spotify: AuthCodeSpotify,
     amount: u32,
@@ -399,12 +1223,18 @@ async fn get_liked_songs(
 
         spawn(async move {
             Ok::<Vec<SavedTrack>, Box<dyn Error + Send>>(
-                spotify_clone
-                    .current_user_saved_tracks_manual(None, Some(current_batch_size), Some(offset))
-                    .await
-                    // make error 'Send'
-                    .map_err(|e| Box::new(e) as Box<dyn Error + Send>)?
-                    .items,
+                // the policy caps how many batches are requested at once
+                requests::request(|| {
+                    spotify_clone.current_user_saved_tracks_manual(
+                        None,
+                        Some(current_batch_size),
+                        Some(offset),
+                    )
+                })
+                .await
+                // make error 'Send'
+                .map_err(|e| Box::new(e) as Box<dyn Error + Send>)?
+                .items,
             )
         })
     });
@@ -416,127 +1246,3 @@ async fn get_liked_songs(
 
     Ok(all_tracks)
 }
-
-async fn search_for_playlist(
-    spotify: AuthCodeSpotify,
-    playlist_name: String,
-) -> Result<Option<PlaylistId<'static>>, Box<dyn Error + Send>> {
-    // currently existing playlists
-    let existing_playlists = spotify
-        .current_user_playlists_manual(Some(50), None)
-        .await
-        // make the error 'Send'
-        .map_err(|e| Box::new(e) as Box<dyn Error + Send>)?;
-
-    Ok(existing_playlists
-        .items
-        .into_iter()
-        .find(|existing_playlist| existing_playlist.name == playlist_name)
-        // get playlist id
-        .map(|playlist| playlist.id))
-}
-
-async fn create_playlist(
-    spotify: AuthCodeSpotify,
-    username: String,
-    playlist_name: String,
-) -> Result<PlaylistId<'static>, Box<dyn Error + Send>> {
-    Ok(spotify
-        // create playlist
-        .user_playlist_create(
-            UserId::from_id(username).expect("Expected username to be valid"),
-            &playlist_name,
-            Some(false),
-            Some(false),
-            None,
-        )
-        .await
-        // make the error 'Send'
-        .map_err(|e| Box::new(e) as Box<dyn Error + Send>)?
-        // get id
-        .id)
-}
-
-/// Removes items if playlist already exists, creates playlist if not
-async fn empty_playlist(
-    spotify: AuthCodeSpotify,
-    username: String,
-    playlist_name: String,
-) -> Result<PlaylistId<'static>, Box<dyn Error + Send>> {
-    // get playlist
-    let searched_playlist = search_for_playlist(spotify.clone(), playlist_name.clone()).await?;
-
-    // empty vec, to clear playlist
-    let empty_items: Vec<PlayableId<'static>> = Vec::new();
-    match searched_playlist {
-        Some(playlist_id) => {
-            spotify
-                .playlist_replace_items(playlist_id.clone(), empty_items)
-                .await
-                // make error 'Send'
-                .map_err(|e| Box::new(e) as Box<dyn Error + Send>)?;
-            Ok(playlist_id)
-        }
-        None => Ok(create_playlist(spotify, username, playlist_name).await?),
-    }
-}
-
-/// Populates the given playlist with the given song id's
-async fn populate_playlist(
-    spotify: AuthCodeSpotify,
-    playlist_id: PlaylistId<'static>,
-    song_ids: Vec<TrackId<'static>>,
-) -> Result<(), Box<dyn Error + Send>> {
-    // Given by the spotify API docs
-    let batch_size = 100;
-
-    let max_retries = 3;
-    let delay_between_retries = Duration::from_secs(2);
-
-    let tasks: Vec<_> = song_ids
-        .chunks(batch_size)
-        .map(|chunk| {
-            let spotify_clone = spotify.clone();
-            let playlist_id_clone = playlist_id.clone();
-
-            // make a owned version of the chunk
-            let chunk_owned = chunk.to_vec();
-
-            async move {
-                let mut retries = 0;
-                loop {
-                    let chunk_vec = chunk_owned
-                        .iter()
-                        .map(|track_id| PlayableId::Track(track_id.clone()))
-                        .collect::<Vec<_>>();
-                    match spotify_clone
-                        .playlist_add_items(playlist_id_clone.clone(), chunk_vec, None)
-                        .await
-                    {
-                        Ok(_) => return Ok(()),
-                        Err(e) if retries < max_retries => {
-                            println!("Retrying due to error: {}", e);
-                            retries += 1;
-                            sleep(delay_between_retries).await;
-                        }
-                        Err(e) => return Err(Box::new(e) as Box<dyn Error + Send>),
-                    }
-                }
-            }
-        })
-        .collect();
-
-    let results = join_all(tasks).await;
-    for result in results {
-        match result {
-            Ok(()) => {
-                // If the task succeeded, you can process the successful addition here.
-            }
-            Err(e) => {
-                println!("Failed to add items to the playlist: {e}");
-            }
-        }
-    }
-
-    Ok(())
-}
@@ -0,0 +1,98 @@
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use std::{
+    error::Error,
+    fs::{create_dir_all, File},
+    io::{BufReader, ErrorKind, Write},
+    path::{Path, PathBuf},
+};
+
+/// A playlist created (or taken over with `--force`) by spotility
+#[derive(Serialize, Deserialize, Debug)]
+pub struct ManagedPlaylist {
+    pub id: String,
+    pub name: String,
+    pub created_at: DateTime<Utc>,
+    pub synced_at: DateTime<Utc>,
+}
+
+/// The playlists spotility may overwrite
+#[derive(Serialize, Deserialize, Debug, Default)]
+pub struct Managed {
+    #[serde(default)]
+    pub playlists: Vec<ManagedPlaylist>,
+}
+
+impl Managed {
+    pub fn contains(&self, playlist_id: &str) -> bool {
+        self.playlists
+            .iter()
+            .any(|playlist| playlist.id == playlist_id)
+    }
+
+    /// Marks the playlist as managed, or updates its name and sync time if it already is
+    pub fn record(&mut self, playlist_id: &str, name: &str, now: DateTime<Utc>) {
+        match self
+            .playlists
+            .iter_mut()
+            .find(|playlist| playlist.id == playlist_id)
+        {
+            Some(playlist) => {
+                playlist.name = name.to_string();
+                playlist.synced_at = now;
+            }
+            None => self.playlists.push(ManagedPlaylist {
+                id: playlist_id.to_string(),
+                name: name.to_string(),
+                created_at: now,
+                synced_at: now,
+            }),
+        }
+    }
+}
+
+/// The managed playlists are stored next to the database, e.g. 'ratings.json' -> 'ratings.playlists.json'
+fn managed_path(db_path: &str) -> PathBuf {
+    Path::new(db_path).with_extension("playlists.json")
+}
+
+pub fn load_managed(db_path: &str) -> Result<Managed, Box<dyn Error>> {
+    match File::open(managed_path(db_path)) {
+        Ok(file) => Ok(serde_json::from_reader(BufReader::new(file))?),
+        // no playlists created yet
+        Err(e) if e.kind() == ErrorKind::NotFound => Ok(Managed::default()),
+        Err(e) => Err(Box::new(e)),
+    }
+}
+
+pub fn save_managed(db_path: &str, managed: &Managed) -> Result<(), Box<dyn Error>> {
+    let path = managed_path(db_path);
+
+    if let Some(parent) = path.parent() {
+        create_dir_all(parent)?;
+    }
+
+    File::create(path)?.write_all(serde_json::to_string(managed)?.as_bytes())?;
+
+    Ok(())
+}
+
+/// Prints the managed playlists, most recently synced first
+pub fn print_managed(managed: &Managed) {
+    if managed.playlists.is_empty() {
+        println!("No managed playlists");
+        return;
+    }
+
+    let mut playlists: Vec<_> = managed.playlists.iter().collect();
+    playlists.sort_by_key(|playlist| std::cmp::Reverse(playlist.synced_at));
+    for playlist in playlists {
+        println!(
+            "{} ({}), created {}, last synced {}",
+            playlist.name,
+            playlist.id,
+            playlist.created_at.format("%Y-%m-%d"),
+            playlist.synced_at.format("%Y-%m-%d %H:%M")
+        );
+    }
+}
@@ -0,0 +1,925 @@
+use crate::{managed::Managed, requests};
+use chrono::Utc;
+use futures::{future::ready, TryStreamExt};
+use rspotify::{
+    model::{
+        EpisodeId, ItemPositions, PlayableItem, PlaylistId, SimplifiedPlaylist, TrackId, UserId,
+    },
+    prelude::*,
+    AuthCodeSpotify, ClientResult,
+};
+use std::{collections::HashMap, error::Error, future::Future};
+
+/// Maximum amount of items per request, given by the spotify API docs
+const BATCH_SIZE: usize = 100;
+/// Amount of moves and inserts above which the playlist is rewritten instead of synced.
+/// A rewrite resets the dates the songs were added, so it is only worth it for e.g. a fresh shuffle
+const REWRITE_THRESHOLD: usize = 100;
+
+/// Finds the playlist with exactly the name among all playlists of the user.
+/// Playlists owned by the user are preferred over collaborative ones of others,
+/// playlists that can't be changed are ignored.
+/// Playlists managed by spotility are preferred over all others, so a hand curated playlist
+/// with the same name doesn't hide the managed one
+async fn search_for_playlist(
+    spotify: &AuthCodeSpotify,
+    username: &str,
+    playlist_name: &str,
+    managed: &Managed,
+) -> Result<Option<PlaylistId<'static>>, Box<dyn Error + Send>> {
+    // all pages of the currently existing playlists
+    let existing_playlists: Vec<SimplifiedPlaylist> = requests::paginate(|limit, offset| {
+        spotify.current_user_playlists_manual(Some(limit), Some(offset))
+    })
+    .try_filter(|existing_playlist| ready(existing_playlist.name == playlist_name))
+    .try_collect()
+    .await
+    // make the error 'Send'
+    .map_err(|e| Box::new(e) as Box<dyn Error + Send>)?;
+
+    let (owned, others): (Vec<_>, Vec<_>) = existing_playlists
+        .into_iter()
+        .partition(|existing_playlist| existing_playlist.owner.id.id() == username);
+    let (collaborative, read_only): (Vec<_>, Vec<_>) = others
+        .into_iter()
+        .partition(|existing_playlist| existing_playlist.collaborative);
+
+    for playlist in &read_only {
+        println!(
+            "Ignoring playlist '{playlist_name}' ({}) by {}, it can't be changed",
+            playlist.id.id(),
+            owner_name(playlist)
+        );
+    }
+
+    let candidates = if owned.is_empty() {
+        collaborative
+    } else {
+        owned
+    };
+    let (managed_candidates, unmanaged): (Vec<_>, Vec<_>) = candidates
+        .into_iter()
+        .partition(|playlist| managed.contains(playlist.id.id()));
+    let candidates = if managed_candidates.is_empty() {
+        unmanaged
+    } else {
+        managed_candidates
+    };
+    if candidates.len() > 1 {
+        println!(
+            "Found {} playlists named '{playlist_name}', using the first one:",
+            candidates.len()
+        );
+        for playlist in &candidates {
+            println!("  {} by {}", playlist.id.id(), owner_name(playlist));
+        }
+    }
+
+    // get playlist id
+    Ok(candidates.into_iter().next().map(|playlist| playlist.id))
+}
+
+fn owner_name(playlist: &SimplifiedPlaylist) -> &str {
+    playlist
+        .owner
+        .display_name
+        .as_deref()
+        .unwrap_or(playlist.owner.id.id())
+}
+
+async fn create_playlist(
+    spotify: AuthCodeSpotify,
+    username: String,
+    playlist_name: String,
+) -> Result<PlaylistId<'static>, Box<dyn Error + Send>> {
+    let user_id = UserId::from_id(username).expect("Expected username to be valid");
+    // not repeated after a timeout, which could create the playlist twice
+    Ok(requests::request_once(|| {
+        // create playlist
+        spotify.user_playlist_create(
+            user_id.clone(),
+            &playlist_name,
+            Some(false),
+            Some(false),
+            None,
+        )
+    })
+    .await
+    // make the error 'Send'
+    .map_err(|e| Box::new(e) as Box<dyn Error + Send>)?
+    // get id
+    .id)
+}
+
+/// Placeholders of description templates: amount of songs, current date,
+/// average rating of the rated songs and name of the playlist
+pub const DESCRIPTION_PLACEHOLDERS: [&str; 4] = ["n", "date", "avg", "name"];
+
+/// Checks that the description template only uses known placeholders
+pub fn parse_description(template: &str) -> Result<String, String> {
+    let mut rest = template;
+    while let Some(start) = rest.find('{') {
+        let end = rest[start..]
+            .find('}')
+            .ok_or_else(|| format!("unclosed '{{' in '{template}'"))?;
+        let placeholder = &rest[start + 1..start + end];
+        if !DESCRIPTION_PLACEHOLDERS.contains(&placeholder) {
+            return Err(format!(
+                "unknown placeholder '{{{placeholder}}}', expected one of {}",
+                DESCRIPTION_PLACEHOLDERS
+                    .map(|name| format!("{{{name}}}"))
+                    .join(", ")
+            ));
+        }
+        rest = &rest[start + end + 1..];
+    }
+
+    Ok(template.to_string())
+}
+
+/// Fills the placeholders of the description template, e.g. "Top {n} as of {date}, avg rating {avg}"
+pub fn render_description(
+    template: &str,
+    songs: usize,
+    average_rating: Option<f32>,
+    playlist_name: &str,
+) -> String {
+    template
+        .replace("{n}", &songs.to_string())
+        .replace("{date}", &Utc::now().format("%Y-%m-%d").to_string())
+        .replace(
+            "{avg}",
+            &average_rating.map_or("none".to_string(), |rating| format!("{rating:.2}")),
+        )
+        .replace("{name}", playlist_name)
+}
+
+/// Details set on the playlist when it's created or synced, `None` leaves them unchanged
+#[derive(Debug, Default)]
+pub struct PlaylistDetails {
+    pub public: Option<bool>,
+    pub collaborative: Option<bool>,
+    pub description: Option<String>,
+}
+
+impl PlaylistDetails {
+    fn is_empty(&self) -> bool {
+        self.public.is_none() && self.collaborative.is_none() && self.description.is_none()
+    }
+
+    fn summary(&self) -> String {
+        let mut details = Vec::new();
+        if let Some(public) = self.public {
+            details.push(if public { "public" } else { "private" }.to_string());
+        }
+        if self.collaborative == Some(true) {
+            details.push("collaborative".to_string());
+        }
+        if let Some(description) = &self.description {
+            details.push(format!("description '{description}'"));
+        }
+        details.join(", ")
+    }
+}
+
+/// How a generated playlist is written
+pub struct PlaylistOptions {
+    pub name: String,
+    pub details: PlaylistDetails,
+    /// Generates a cover from the album arts of the first songs
+    pub cover: bool,
+    /// Overwrites the playlist even if it isn't managed by spotility
+    pub force: bool,
+    /// Only prints the changes
+    pub dry_run: bool,
+}
+
+/// Syncs the songs into the playlist with the name, creating it if it doesn't exist yet.
+/// Existing playlists are only changed if they are managed by spotility, or with `force`.
+/// Created and changed playlists are recorded in `managed`.
+/// Returns the playlist, `None` if it was left alone or doesn't exist in a dry run
+pub async fn write_playlist(
+    spotify: &AuthCodeSpotify,
+    username: String,
+    song_ids: Vec<TrackId<'static>>,
+    managed: &mut Managed,
+    options: &PlaylistOptions,
+) -> Result<Option<PlaylistId<'static>>, Box<dyn Error + Send>> {
+    let playlist_name = &options.name;
+    let playlist_id = match search_for_playlist(spotify, &username, playlist_name, managed).await? {
+        // only unmanaged playlists have the name
+        Some(playlist_id) if !managed.contains(playlist_id.id()) && !options.force => {
+            println!(
+                "Playlist '{playlist_name}' ({}) wasn't created by spotility, not changing it. Use --force to overwrite it",
+                playlist_id.id()
+            );
+            return Ok(None);
+        }
+        Some(playlist_id) => playlist_id,
+        None if options.dry_run => {
+            println!("Dry run, not creating playlist '{playlist_name}'");
+            let wanted: Vec<String> = song_ids.iter().map(|song_id| song_id.uri()).collect();
+            plan_sync(&[], &wanted).print();
+            if !options.details.is_empty() {
+                println!("~ details: {}", options.details.summary());
+            }
+            return Ok(None);
+        }
+        None => {
+            let playlist_id =
+                create_playlist(spotify.clone(), username, playlist_name.clone()).await?;
+            // recorded right away, so a failed sync doesn't leave an unmanaged playlist behind
+            managed.record(playlist_id.id(), playlist_name, Utc::now());
+            playlist_id
+        }
+    };
+
+    sync_playlist(spotify, playlist_id.clone(), song_ids, options.dry_run).await?;
+    change_details(spotify, &playlist_id, &options.details, options.dry_run).await?;
+    if !options.dry_run {
+        managed.record(playlist_id.id(), playlist_name, Utc::now());
+    }
+
+    Ok(Some(playlist_id))
+}
+
+/// Sets the details of the playlist, if any are given
+async fn change_details(
+    spotify: &AuthCodeSpotify,
+    playlist_id: &PlaylistId<'static>,
+    details: &PlaylistDetails,
+    dry_run: bool,
+) -> Result<(), Box<dyn Error + Send>> {
+    if details.is_empty() {
+        return Ok(());
+    }
+    if dry_run {
+        println!("Dry run, not changing the details: {}", details.summary());
+        return Ok(());
+    }
+
+    with_retries(|| {
+        spotify.playlist_change_detail(
+            playlist_id.clone(),
+            None,
+            details.public,
+            details.description.as_deref(),
+            details.collaborative,
+        )
+    })
+    .await?;
+    println!("Playlist details set: {}", details.summary());
+
+    Ok(())
+}
+
+/// A block of items moved to an earlier position
+#[derive(Debug, PartialEq)]
+pub struct Move {
+    pub range_start: usize,
+    pub insert_before: usize,
+    pub range_length: usize,
+}
+
+/// Items inserted at a position
+#[derive(Debug, PartialEq)]
+pub struct Insert {
+    pub position: usize,
+    pub uris: Vec<String>,
+}
+
+/// The changes turning the current items of a playlist into the wanted ones,
+/// to be applied in order: removals, moves, inserts
+#[derive(Debug, Default, PartialEq)]
+pub struct SyncPlan {
+    /// Positions of the removed items with their uri, last position first
+    pub removals: Vec<(usize, String)>,
+    pub moves: Vec<Move>,
+    pub inserts: Vec<Insert>,
+}
+
+impl SyncPlan {
+    pub fn is_empty(&self) -> bool {
+        self.removals.is_empty() && self.moves.is_empty() && self.inserts.is_empty()
+    }
+
+    fn summary(&self) -> String {
+        format!(
+            "{} songs removed, {} blocks of songs moved and {} songs added",
+            self.removals.len(),
+            self.moves.len(),
+            self.inserts
+                .iter()
+                .map(|insert| insert.uris.len())
+                .sum::<usize>()
+        )
+    }
+
+    /// Prints every change of the plan
+    fn print(&self) {
+        println!("{}", self.summary());
+        for (position, uri) in &self.removals {
+            println!("  - {uri} (position {position})");
+        }
+        for block in &self.moves {
+            println!(
+                "  ~ {} songs from position {} to {}",
+                block.range_length, block.range_start, block.insert_before
+            );
+        }
+        for insert in &self.inserts {
+            for (offset, uri) in insert.uris.iter().enumerate() {
+                println!("  + {uri} (position {})", insert.position + offset);
+            }
+        }
+    }
+}
+
+/// Plans the sync of the `current` items (`None` for local files) to the `wanted` uris.
+/// Items staying in the playlist keep their entry (and with it the time they were added),
+/// local files can't be removed through the API and are moved to the end
+pub fn plan_sync(current: &[Option<String>], wanted: &[String]) -> SyncPlan {
+    let mut plan = SyncPlan::default();
+
+    // keep as many occurrences of each item as are wanted
+    let mut wanted_counts: HashMap<&str, usize> = HashMap::new();
+    for uri in wanted {
+        *wanted_counts.entry(uri).or_default() += 1;
+    }
+    let mut kept = Vec::new();
+    for (position, item) in current.iter().enumerate() {
+        match item {
+            Some(uri) => match wanted_counts.get_mut(uri.as_str()) {
+                Some(count) if *count > 0 => {
+                    *count -= 1;
+                    kept.push(item.clone());
+                }
+                _ => plan.removals.push((position, uri.clone())),
+            },
+            None => kept.push(None),
+        }
+    }
+    // removing from the back keeps the remaining positions valid
+    plan.removals.reverse();
+
+    // wanted items without a kept occurrence are new
+    let mut kept_counts: HashMap<&str, usize> = HashMap::new();
+    for uri in kept.iter().flatten() {
+        *kept_counts.entry(uri).or_default() += 1;
+    }
+    let is_new: Vec<bool> = wanted
+        .iter()
+        .map(|uri| match kept_counts.get_mut(uri.as_str()) {
+            Some(count) if *count > 0 => {
+                *count -= 1;
+                false
+            }
+            _ => true,
+        })
+        .collect();
+
+    // bring the kept items into the wanted order, moving blocks that are already in order together
+    let target: Vec<Option<String>> = wanted
+        .iter()
+        .zip(&is_new)
+        .filter(|(_, is_new)| !**is_new)
+        .map(|(uri, _)| Some(uri.clone()))
+        .chain(kept.iter().filter(|item| item.is_none()).cloned())
+        .collect();
+    for i in 0..target.len() {
+        if kept[i] == target[i] {
+            continue;
+        }
+        let range_start = i + kept[i..]
+            .iter()
+            .position(|item| *item == target[i])
+            .expect("kept and target contain the same items");
+        let mut range_length = 1;
+        while range_start + range_length < kept.len()
+            && i + range_length < target.len()
+            && kept[range_start + range_length] == target[i + range_length]
+        {
+            range_length += 1;
+        }
+
+        let block: Vec<_> = kept
+            .drain(range_start..range_start + range_length)
+            .collect();
+        kept.splice(i..i, block);
+        plan.moves.push(Move {
+            range_start,
+            insert_before: i,
+            range_length,
+        });
+    }
+
+    // insert the new items at their position, in runs
+    let mut position = 0;
+    while position < wanted.len() {
+        if !is_new[position] {
+            position += 1;
+            continue;
+        }
+        let run_length = is_new[position..]
+            .iter()
+            .take(BATCH_SIZE)
+            .take_while(|is_new| **is_new)
+            .count();
+        plan.inserts.push(Insert {
+            position,
+            uris: wanted[position..position + run_length].to_vec(),
+        });
+        position += run_length;
+    }
+
+    plan
+}
+
+/// The requests a playlist sync is made of, so the sync can run against a fake client
+trait PlaylistClient {
+    /// Snapshot id and uris of the items of the playlist (`None` for local files)
+    async fn items(
+        &self,
+        playlist_id: &PlaylistId<'static>,
+    ) -> ClientResult<(String, Vec<Option<String>>)>;
+
+    /// Removes the items at the positions, returns the new snapshot id
+    async fn remove(
+        &self,
+        playlist_id: &PlaylistId<'static>,
+        positions: &[(&str, Vec<u32>)],
+        snapshot_id: &str,
+    ) -> ClientResult<String>;
+
+    /// Moves the block of items, returns the new snapshot id
+    async fn reorder(
+        &self,
+        playlist_id: &PlaylistId<'static>,
+        block: &Move,
+        snapshot_id: &str,
+    ) -> ClientResult<String>;
+
+    /// Inserts the items at their position, returns the new snapshot id
+    async fn insert(
+        &self,
+        playlist_id: &PlaylistId<'static>,
+        insert: &Insert,
+    ) -> ClientResult<String>;
+
+    /// Replaces all items with at most `BATCH_SIZE` uris
+    async fn replace(&self, playlist_id: &PlaylistId<'static>, uris: &[String])
+        -> ClientResult<()>;
+}
+
+impl PlaylistClient for AuthCodeSpotify {
+    async fn items(
+        &self,
+        playlist_id: &PlaylistId<'static>,
+    ) -> ClientResult<(String, Vec<Option<String>>)> {
+        let snapshot_id = requests::request(|| self.playlist(playlist_id.clone(), None, None))
+            .await?
+            .snapshot_id;
+        let items = requests::paginate(|limit, offset| {
+            self.playlist_items_manual(playlist_id.clone(), None, None, Some(limit), Some(offset))
+        })
+        .try_collect::<Vec<_>>()
+        .await?
+        .into_iter()
+        .map(|item| match item.track {
+            Some(PlayableItem::Track(track)) => track.id.map(|id| id.uri()),
+            Some(PlayableItem::Episode(episode)) => Some(episode.id.uri()),
+            None => None,
+        })
+        .collect();
+
+        Ok((snapshot_id, items))
+    }
+
+    async fn remove(
+        &self,
+        playlist_id: &PlaylistId<'static>,
+        positions: &[(&str, Vec<u32>)],
+        snapshot_id: &str,
+    ) -> ClientResult<String> {
+        let items: Vec<_> = positions
+            .iter()
+            .map(|(uri, item_positions)| ItemPositions {
+                id: playable_id(uri),
+                positions: item_positions,
+            })
+            .collect();
+
+        Ok(self
+            .playlist_remove_specific_occurrences_of_items(
+                playlist_id.clone(),
+                items,
+                Some(snapshot_id),
+            )
+            .await?
+            .snapshot_id)
+    }
+
+    async fn reorder(
+        &self,
+        playlist_id: &PlaylistId<'static>,
+        block: &Move,
+        snapshot_id: &str,
+    ) -> ClientResult<String> {
+        Ok(self
+            .playlist_reorder_items(
+                playlist_id.clone(),
+                Some(block.range_start as i32),
+                Some(block.insert_before as i32),
+                Some(block.range_length as u32),
+                Some(snapshot_id),
+            )
+            .await?
+            .snapshot_id)
+    }
+
+    async fn insert(
+        &self,
+        playlist_id: &PlaylistId<'static>,
+        insert: &Insert,
+    ) -> ClientResult<String> {
+        Ok(self
+            .playlist_add_items(
+                playlist_id.clone(),
+                insert.uris.iter().map(|uri| playable_id(uri)),
+                Some(insert.position as u32),
+            )
+            .await?
+            .snapshot_id)
+    }
+
+    async fn replace(
+        &self,
+        playlist_id: &PlaylistId<'static>,
+        uris: &[String],
+    ) -> ClientResult<()> {
+        self.playlist_replace_items(playlist_id.clone(), uris.iter().map(|uri| playable_id(uri)))
+            .await
+    }
+}
+
+fn playable_id(uri: &str) -> PlayableId<'_> {
+    match TrackId::from_uri(uri) {
+        Ok(track_id) => PlayableId::Track(track_id),
+        Err(_) => PlayableId::Episode(
+            EpisodeId::from_uri(uri).expect("playlist items are tracks or episodes"),
+        ),
+    }
+}
+
+/// Runs the request with the shared retry policy
+async fn with_retries<T, F: Future<Output = ClientResult<T>>>(
+    request: impl FnMut() -> F,
+) -> Result<T, Box<dyn Error + Send>> {
+    requests::request(request)
+        .await
+        // make the error 'Send'
+        .map_err(|e| Box::new(e) as Box<dyn Error + Send>)
+}
+
+/// Runs the insert with the shared retry policy. Inserts aren't guarded by a snapshot,
+/// so they are not retried if they may have reached spotify already
+async fn insert_once(
+    client: &impl PlaylistClient,
+    playlist_id: &PlaylistId<'static>,
+    insert: &Insert,
+) -> Result<String, Box<dyn Error + Send>> {
+    requests::request_once(|| client.insert(playlist_id, insert))
+        .await
+        .map_err(|e| Box::new(e) as Box<dyn Error + Send>)
+}
+
+/// Changes the playlist to contain exactly the songs, in order,
+/// only removing, moving and adding the songs that differ.
+/// The changes are made one after another, each against the snapshot of the previous one,
+/// and every request is retried on its own
+async fn sync_playlist(
+    client: &impl PlaylistClient,
+    playlist_id: PlaylistId<'static>,
+    song_ids: Vec<TrackId<'static>>,
+    dry_run: bool,
+) -> Result<(), Box<dyn Error + Send>> {
+    // paginated, so every page is retried on its own
+    let (mut snapshot_id, current) = client
+        .items(&playlist_id)
+        .await
+        .map_err(|e| Box::new(e) as Box<dyn Error + Send>)?;
+    let wanted: Vec<String> = song_ids.iter().map(|song_id| song_id.uri()).collect();
+    let plan = plan_sync(&current, &wanted);
+
+    if plan.is_empty() {
+        println!("Playlist is up to date");
+        return Ok(());
+    }
+
+    // e.g. a fresh shuffle moves almost every song, one request per move.
+    // Replacing loses local files, so playlists with them are always synced
+    let requests = plan.moves.len() + plan.inserts.len();
+    let rewrite_requests = wanted.len().div_ceil(BATCH_SIZE).max(1);
+    if requests > REWRITE_THRESHOLD
+        && requests > rewrite_requests
+        && current.iter().all(Option::is_some)
+    {
+        if dry_run {
+            println!(
+                "Dry run, not rewriting the playlist with {} songs ({})",
+                wanted.len(),
+                plan.summary()
+            );
+            return Ok(());
+        }
+        return rewrite_playlist(client, &playlist_id, &wanted, &plan).await;
+    }
+
+    if dry_run {
+        println!("Dry run, not changing the playlist");
+        plan.print();
+        return Ok(());
+    }
+
+    for chunk in plan.removals.chunks(BATCH_SIZE) {
+        // the API takes the positions grouped by item
+        let mut positions: Vec<(&str, Vec<u32>)> = Vec::new();
+        for (position, uri) in chunk {
+            match positions.iter_mut().find(|(other, _)| other == uri) {
+                Some((_, item_positions)) => item_positions.push(*position as u32),
+                None => positions.push((uri, vec![*position as u32])),
+            }
+        }
+
+        snapshot_id =
+            with_retries(|| client.remove(&playlist_id, &positions, &snapshot_id)).await?;
+    }
+
+    for block in &plan.moves {
+        snapshot_id = with_retries(|| client.reorder(&playlist_id, block, &snapshot_id)).await?;
+    }
+
+    // in order, so every insert lands behind the songs before it
+    for insert in &plan.inserts {
+        insert_once(client, &playlist_id, insert).await?;
+    }
+
+    println!("Playlist synced: {}", plan.summary());
+
+    Ok(())
+}
+
+/// Replaces the items of the playlist with the first chunk of songs and appends the rest,
+/// for plans that would take more requests than that
+async fn rewrite_playlist(
+    client: &impl PlaylistClient,
+    playlist_id: &PlaylistId<'static>,
+    wanted: &[String],
+    plan: &SyncPlan,
+) -> Result<(), Box<dyn Error + Send>> {
+    let mut chunks = wanted.chunks(BATCH_SIZE);
+    let first = chunks.next().unwrap_or_default();
+    with_retries(|| client.replace(playlist_id, first)).await?;
+
+    // in order, so every chunk lands behind the songs before it
+    let mut position = first.len();
+    for chunk in chunks {
+        let insert = Insert {
+            position,
+            uris: chunk.to_vec(),
+        };
+        insert_once(client, playlist_id, &insert).await?;
+        position += chunk.len();
+    }
+
+    println!("Playlist rewritten: {}", plan.summary());
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        requests::RetryPolicy,
+        test_util::{playlist_id, song_ids},
+    };
+    use std::{cell::RefCell, io};
+
+    /// Playlist kept in memory, failing the requests listed in `failures` once
+    #[derive(Default)]
+    struct FakeClient {
+        /// Uri and the request that added the item
+        items: RefCell<Vec<(Option<String>, usize)>>,
+        snapshot: RefCell<usize>,
+        requests: RefCell<usize>,
+        failures: Vec<usize>,
+    }
+
+    impl FakeClient {
+        fn with_items(uris: &[&str]) -> Self {
+            Self {
+                items: RefCell::new(uris.iter().map(|uri| (Some(uri.to_string()), 0)).collect()),
+                ..Self::default()
+            }
+        }
+
+        /// Counts the request, fails it if listed and checks the snapshot it is made against
+        fn request(&self, snapshot_id: Option<&str>) -> ClientResult<usize> {
+            *self.requests.borrow_mut() += 1;
+            let request = *self.requests.borrow();
+            if self.failures.contains(&request) {
+                return Err(io::Error::other("fake failure").into());
+            }
+            if let Some(snapshot_id) = snapshot_id {
+                assert_eq!(
+                    snapshot_id,
+                    self.snapshot.borrow().to_string(),
+                    "stale snapshot"
+                );
+            }
+            *self.snapshot.borrow_mut() += 1;
+            Ok(request)
+        }
+
+        fn uris(&self) -> Vec<String> {
+            self.items
+                .borrow()
+                .iter()
+                .flat_map(|(uri, _)| uri.clone())
+                .collect()
+        }
+    }
+
+    impl PlaylistClient for FakeClient {
+        async fn items(
+            &self,
+            _: &PlaylistId<'static>,
+        ) -> ClientResult<(String, Vec<Option<String>>)> {
+            Ok((
+                self.snapshot.borrow().to_string(),
+                self.items
+                    .borrow()
+                    .iter()
+                    .map(|(uri, _)| uri.clone())
+                    .collect(),
+            ))
+        }
+
+        async fn remove(
+            &self,
+            _: &PlaylistId<'static>,
+            positions: &[(&str, Vec<u32>)],
+            snapshot_id: &str,
+        ) -> ClientResult<String> {
+            self.request(Some(snapshot_id))?;
+            let mut positions: Vec<_> = positions
+                .iter()
+                .flat_map(|(uri, item_positions)| {
+                    item_positions
+                        .iter()
+                        .map(move |position| (*position as usize, *uri))
+                })
+                .collect();
+            positions.sort_by_key(|(position, _)| std::cmp::Reverse(*position));
+            let mut items = self.items.borrow_mut();
+            for (position, uri) in positions {
+                assert_eq!(items[position].0.as_deref(), Some(uri));
+                items.remove(position);
+            }
+            Ok(self.snapshot.borrow().to_string())
+        }
+
+        async fn reorder(
+            &self,
+            _: &PlaylistId<'static>,
+            block: &Move,
+            snapshot_id: &str,
+        ) -> ClientResult<String> {
+            self.request(Some(snapshot_id))?;
+            let mut items = self.items.borrow_mut();
+            let moved: Vec<_> = items
+                .drain(block.range_start..block.range_start + block.range_length)
+                .collect();
+            items.splice(block.insert_before..block.insert_before, moved);
+            Ok(self.snapshot.borrow().to_string())
+        }
+
+        async fn insert(&self, _: &PlaylistId<'static>, insert: &Insert) -> ClientResult<String> {
+            let request = self.request(None)?;
+            assert!(insert.uris.len() <= BATCH_SIZE);
+            self.items.borrow_mut().splice(
+                insert.position..insert.position,
+                insert.uris.iter().map(|uri| (Some(uri.clone()), request)),
+            );
+            Ok(self.snapshot.borrow().to_string())
+        }
+
+        async fn replace(&self, _: &PlaylistId<'static>, uris: &[String]) -> ClientResult<()> {
+            let request = self.request(None)?;
+            assert!(uris.len() <= BATCH_SIZE);
+            *self.items.borrow_mut() = uris
+                .iter()
+                .map(|uri| (Some(uri.clone()), request))
+                .collect();
+            Ok(())
+        }
+    }
+
+    fn uris(song_ids: &[TrackId<'static>]) -> Vec<String> {
+        song_ids.iter().map(|song_id| song_id.uri()).collect()
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn keeps_order_over_multiple_chunks() {
+        let client = FakeClient {
+            // one failure per chunk
+            failures: vec![1, 3, 5],
+            ..FakeClient::default()
+        };
+        let song_ids = song_ids(0..250);
+
+        sync_playlist(&client, playlist_id(), song_ids.clone(), false)
+            .await
+            .unwrap();
+
+        assert_eq!(client.uris(), uris(&song_ids));
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn only_changes_differing_songs() {
+        let old = song_ids(0..150);
+        let client =
+            FakeClient::with_items(&uris(&old).iter().map(String::as_str).collect::<Vec<_>>());
+        // drop some songs, move a block to the front and add new ones at the end
+        let mut wanted: Vec<_> = old[120..140].to_vec();
+        wanted.extend(old[..100].iter().step_by(2).cloned());
+        wanted.extend(song_ids(300..380));
+
+        sync_playlist(&client, playlist_id(), wanted.clone(), false)
+            .await
+            .unwrap();
+
+        assert_eq!(client.uris(), uris(&wanted));
+        // songs that stayed keep their entry
+        let items = client.items.borrow();
+        for (uri, added_by) in items.iter() {
+            let kept = old.iter().any(|song_id| Some(song_id.uri()) == *uri);
+            assert_eq!(*added_by == 0, kept);
+        }
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn rewrites_heavily_reordered_playlist() {
+        let old = song_ids(0..300);
+        let client =
+            FakeClient::with_items(&uris(&old).iter().map(String::as_str).collect::<Vec<_>>());
+        let wanted: Vec<_> = old.iter().rev().cloned().collect();
+
+        sync_playlist(&client, playlist_id(), wanted.clone(), false)
+            .await
+            .unwrap();
+
+        assert_eq!(client.uris(), uris(&wanted));
+        // one replace and two appends instead of a move per song
+        assert_eq!(*client.requests.borrow(), 3);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn syncs_few_moves_by_diff() {
+        let old = song_ids(0..50);
+        let client =
+            FakeClient::with_items(&uris(&old).iter().map(String::as_str).collect::<Vec<_>>());
+        // e.g. two songs changed their rating
+        let mut wanted = old.clone();
+        wanted.swap(3, 10);
+        wanted.swap(20, 40);
+
+        sync_playlist(&client, playlist_id(), wanted.clone(), false)
+            .await
+            .unwrap();
+
+        assert_eq!(client.uris(), uris(&wanted));
+        // no song was replaced
+        assert!(client
+            .items
+            .borrow()
+            .iter()
+            .all(|(_, added_by)| *added_by == 0));
+        assert!(*client.requests.borrow() <= 4);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn gives_up_after_retries() {
+        let client = FakeClient {
+            failures: (1..=RetryPolicy::default().retries as usize + 1).collect(),
+            ..FakeClient::default()
+        };
+
+        assert!(
+            sync_playlist(&client, playlist_id(), song_ids(0..10), false)
+                .await
+                .is_err()
+        );
+    }
+}
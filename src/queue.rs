@@ -0,0 +1,90 @@
+use crate::{
+    requests,
+    weights::{display_name, WeightedSong},
+};
+use rspotify::{
+    model::{PlayableItem, TrackId},
+    prelude::*,
+    AuthCodeSpotify,
+};
+use std::error::Error;
+
+/// Finds the id of the device with the given id or name
+async fn find_device(
+    spotify: &AuthCodeSpotify,
+    device: &str,
+) -> Result<Option<String>, Box<dyn Error>> {
+    let devices = requests::request(|| spotify.device()).await?;
+
+    match devices.iter().find(|candidate| {
+        candidate.id.as_deref() == Some(device) || candidate.name.eq_ignore_ascii_case(device)
+    }) {
+        Some(found) => Ok(found.id.clone()),
+        None => {
+            println!("Device '{device}' not found, available devices:");
+            for available in &devices {
+                println!(
+                    "  {} ({}){}",
+                    available.name,
+                    available.id.as_deref().unwrap_or("no id"),
+                    if available.is_active { " - active" } else { "" }
+                );
+            }
+            Ok(None)
+        }
+    }
+}
+
+/// Ids of the currently playing and queued songs, which count towards the repeat window
+pub async fn current_queue(spotify: &AuthCodeSpotify) -> Result<Vec<String>, Box<dyn Error>> {
+    let current_queue = requests::request(|| spotify.current_user_queue()).await?;
+
+    Ok(current_queue
+        .currently_playing
+        .into_iter()
+        .chain(current_queue.queue)
+        .filter_map(|item| match item {
+            PlayableItem::Track(track) => track.id.map(|id| id.id().to_string()),
+            PlayableItem::Episode(_) => None,
+        })
+        .collect())
+}
+
+/// Adds the samples to the playback queue of the device (the active one if `None`).
+/// If `dry_run` is set, only prints the songs
+pub async fn queue(
+    spotify: &AuthCodeSpotify,
+    samples: &[&WeightedSong],
+    device: Option<&str>,
+    dry_run: bool,
+) -> Result<(), Box<dyn Error>> {
+    let device_id = match device {
+        Some(device) => match find_device(spotify, device).await? {
+            Some(device_id) => Some(device_id),
+            None => return Ok(()),
+        },
+        None => None,
+    };
+
+    if dry_run {
+        println!("Dry run, not queueing {} songs", samples.len());
+        for weighted_song in samples {
+            println!("  {}", display_name(weighted_song));
+        }
+        return Ok(());
+    }
+
+    // queue one after another, to keep the order
+    for weighted_song in samples {
+        let track_id = TrackId::from_id(weighted_song.song_id.as_str())?;
+        // not repeated after a timeout, which could queue the song twice
+        requests::request_once(|| {
+            spotify.add_item_to_queue(PlayableId::Track(track_id.clone()), device_id.as_deref())
+        })
+        .await?;
+    }
+
+    println!("Queued {} songs", samples.len());
+
+    Ok(())
+}
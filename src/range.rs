@@ -0,0 +1,71 @@
+use chrono::{DateTime, Days, Months, NaiveDate, Utc};
+
+/// Range of the times songs were added, both ends are optional
+#[derive(Debug, Clone, Copy, Default)]
+pub struct DateRange {
+    /// Inclusive
+    pub since: Option<DateTime<Utc>>,
+    /// Exclusive
+    pub until: Option<DateTime<Utc>>,
+}
+
+impl DateRange {
+    pub fn contains(&self, time: DateTime<Utc>) -> bool {
+        self.since.is_none_or(|since| time >= since) && self.until.is_none_or(|until| time < until)
+    }
+
+    /// Unknown times are only contained in unbounded ranges
+    pub fn contains_known(&self, time: Option<DateTime<Utc>>) -> bool {
+        time.map_or_else(|| self.is_unbounded(), |time| self.contains(time))
+    }
+
+    pub fn is_unbounded(&self) -> bool {
+        self.since.is_none() && self.until.is_none()
+    }
+}
+
+/// Parses the start of a range, a date starts at its beginning
+pub fn parse_since(input: &str) -> Result<DateTime<Utc>, String> {
+    parse_time(input, false)
+}
+
+/// Parses the end of a range, a date ends at its end
+pub fn parse_until(input: &str) -> Result<DateTime<Utc>, String> {
+    parse_time(input, true)
+}
+
+/// Parses an absolute date ('2024-06-21') or a duration before now ('30d', '2w', '6mo', '1y')
+fn parse_time(input: &str, end_of_day: bool) -> Result<DateTime<Utc>, String> {
+    let input = input.trim();
+
+    if let Ok(date) = NaiveDate::parse_from_str(input, "%Y-%m-%d") {
+        let date = if end_of_day {
+            date.checked_add_days(Days::new(1))
+                .ok_or_else(|| format!("date '{input}' out of range"))?
+        } else {
+            date
+        };
+        return Ok(date
+            .and_hms_opt(0, 0, 0)
+            .expect("midnight exists")
+            .and_utc());
+    }
+
+    let split = input
+        .find(|c: char| !c.is_ascii_digit())
+        .ok_or_else(|| format!("missing unit in '{input}', expected e.g. 30d or 6mo"))?;
+    let (amount, unit) = input.split_at(split);
+    let amount: u32 = amount.parse().map_err(|_| {
+        format!("expected a date like 2024-06-21 or a duration like 30d, got '{input}'")
+    })?;
+
+    let now = Utc::now();
+    match unit {
+        "d" => now.checked_sub_days(Days::new(amount.into())),
+        "w" => now.checked_sub_days(Days::new(u64::from(amount) * 7)),
+        "mo" => now.checked_sub_months(Months::new(amount)),
+        "y" => now.checked_sub_months(Months::new(amount.saturating_mul(12))),
+        other => return Err(format!("unknown unit '{other}', expected d, w, mo or y")),
+    }
+    .ok_or_else(|| format!("duration '{input}' out of range"))
+}
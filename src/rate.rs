@@ -0,0 +1,227 @@
+use crate::{
+    db::{load_hashmap, make_readable, parse_rating, TimeRating, DEFAULT_RATING},
+    journal::save_with_journal,
+    refs, requests,
+};
+use futures::TryStreamExt;
+use rspotify::{
+    model::{AlbumId, PlayableItem, PlaylistId, TrackId},
+    prelude::*,
+    AuthCodeSpotify,
+};
+use std::{
+    collections::HashSet,
+    error::Error,
+    fs::File,
+    io::{self, BufReader, Read},
+};
+
+/// Rates all songs listed in the given file ('-' for stdin) with one load/save of the database
+pub fn rate_batch(
+    db_path: String,
+    batch_path: &str,
+    context: Option<&str>,
+    dry_run: bool,
+) -> Result<(), Box<dyn Error>> {
+    // read batch
+    let mut batch = String::new();
+    match batch_path {
+        "-" => io::stdin().read_to_string(&mut batch)?,
+        path => BufReader::new(File::open(path)?).read_to_string(&mut batch)?,
+    };
+
+    // get ratings db
+    let mut ratings = match load_hashmap(db_path.clone()) {
+        Ok(hashmap) => hashmap,
+        Err(e) => {
+            println!("Error loading database: {e}");
+            return Ok(());
+        }
+    };
+
+    let before = ratings.clone();
+    let mut changed = 0;
+    let mut invalid = 0;
+    for (line_number, line) in batch
+        .lines()
+        .enumerate()
+        .map(|(i, line)| (i + 1, line.trim()))
+    {
+        // skip empty lines and comments
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+
+        let mut parts = line.split_whitespace();
+        let (Some(track_ref), Some(rating), None) = (parts.next(), parts.next(), parts.next())
+        else {
+            println!("line {line_number}: expected '<track-ref> <rating>', got '{line}'");
+            invalid += 1;
+            continue;
+        };
+        let Some(track_id) = refs::parse_track_ref(track_ref) else {
+            println!("line {line_number}: invalid track reference '{track_ref}'");
+            invalid += 1;
+            continue;
+        };
+        let Some(rating) = parse_rating(rating) else {
+            println!("line {line_number}: invalid rating '{rating}'");
+            invalid += 1;
+            continue;
+        };
+        let Some(time_rating) = ratings.get_mut(track_id.id()) else {
+            println!(
+                "line {line_number}: song {} not in local database",
+                track_id.id()
+            );
+            invalid += 1;
+            continue;
+        };
+
+        // print change
+        println!(
+            "{}: {} -> {}",
+            track_id.id(),
+            make_readable(time_rating.rating_in(context)),
+            make_readable(rating)
+        );
+
+        // apply change
+        time_rating.rate_in(context, rating);
+        changed += 1;
+    }
+
+    println!("{changed} songs rated, {invalid} invalid lines");
+
+    save_with_journal(db_path, "rate --batch", &before, &ratings, dry_run)
+}
+
+/// Fetches all tracks of a playlist
+pub async fn get_playlist_tracks(
+    spotify: &AuthCodeSpotify,
+    playlist_id: PlaylistId<'static>,
+) -> Result<(String, Vec<TrackId<'static>>), Box<dyn Error>> {
+    let name = requests::request(|| spotify.playlist(playlist_id.clone(), None, None))
+        .await?
+        .name;
+
+    let tracks = requests::paginate(|limit, offset| {
+        spotify.playlist_items_manual(playlist_id.clone(), None, None, Some(limit), Some(offset))
+    })
+    .try_collect::<Vec<_>>()
+    .await?
+    .into_iter()
+    // skip episodes and local files
+    .filter_map(|item| match item.track {
+        Some(PlayableItem::Track(track)) => track.id,
+        _ => None,
+    })
+    .collect();
+
+    Ok((name, tracks))
+}
+
+/// Fetches all tracks of an album
+pub async fn get_album_tracks(
+    spotify: &AuthCodeSpotify,
+    album_id: AlbumId<'static>,
+) -> Result<(String, Vec<TrackId<'static>>), Box<dyn Error>> {
+    let name = requests::request(|| spotify.album(album_id.clone(), None))
+        .await?
+        .name;
+
+    let tracks = requests::paginate(|limit, offset| {
+        spotify.album_track_manual(album_id.clone(), None, Some(limit), Some(offset))
+    })
+    .try_collect::<Vec<_>>()
+    .await?
+    .into_iter()
+    .filter_map(|track| track.id)
+    .collect();
+
+    Ok((name, tracks))
+}
+
+/// Rates all given tracks, inserting the ones missing from the database
+pub fn rate_collection(
+    db_path: String,
+    collection_name: &str,
+    tracks: Vec<TrackId<'static>>,
+    rating: f32,
+    context: Option<&str>,
+    only_unrated: bool,
+    dry_run: bool,
+) -> Result<(), Box<dyn Error>> {
+    // get ratings db
+    let mut ratings = match load_hashmap(db_path.clone()) {
+        Ok(hashmap) => hashmap,
+        Err(e) => {
+            println!("Error loading database: {e}");
+            return Ok(());
+        }
+    };
+
+    // collections may contain a song several times
+    let mut seen = HashSet::new();
+    let tracks: Vec<_> = tracks
+        .into_iter()
+        .filter(|track_id| seen.insert(track_id.clone()))
+        .collect();
+
+    let missing = tracks
+        .iter()
+        .filter(|track_id| !ratings.contains_key(track_id.id()))
+        .count();
+    let unrated = tracks
+        .iter()
+        .filter(|track_id| {
+            ratings
+                .get(track_id.id())
+                .is_some_and(|time_rating| time_rating.is_unrated_in(context))
+        })
+        .count();
+    let rated = tracks.len() - missing - unrated;
+
+    // print summary and get user confirmation
+    println!("'{collection_name}': {} songs", tracks.len());
+    println!("  {missing} not in the local database (will be added)");
+    println!("  {unrated} unrated");
+    println!(
+        "  {rated} already rated{}",
+        if only_unrated {
+            " (skipped)"
+        } else {
+            " (will be overwritten)"
+        }
+    );
+    println!("Rating as {} -- Continue? y/N", make_readable(rating));
+    if !confirm()? {
+        return Ok(());
+    }
+
+    let before = ratings.clone();
+    for track_id in tracks {
+        // the songs weren't liked, update-db adds the time once they are
+        let time_rating = ratings
+            .entry(track_id.id().to_string())
+            .or_insert(TimeRating::new(None, DEFAULT_RATING));
+
+        if only_unrated && !time_rating.is_unrated_in(context) {
+            continue;
+        }
+        time_rating.rate_in(context, rating);
+    }
+
+    save_with_journal(db_path, "rate --collection", &before, &ratings, dry_run)
+}
+
+/// Reads a yes/no answer from stdin, defaulting to no
+fn confirm() -> Result<bool, Box<dyn Error>> {
+    let mut confirmation_buffer = String::new();
+    io::stdin().read_line(&mut confirmation_buffer)?;
+
+    Ok(matches!(
+        confirmation_buffer.trim(),
+        "y" | "Y" | "yes" | "Yes"
+    ))
+}
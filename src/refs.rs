@@ -0,0 +1,73 @@
+use rspotify::model::{AlbumId, ArtistId, PlaylistId, TrackId};
+
+/// Extracts the id from a spotify reference of the given type.
+/// Accepts bare id's, URIs (`spotify:track:<id>`) and URLs (`https://open.spotify.com/track/<id>?si=...`)
+fn extract_id<'a>(kind: &str, input: &'a str) -> &'a str {
+    let input = input.trim();
+
+    // URI
+    if let Some(id) = input
+        .strip_prefix("spotify:")
+        .and_then(|rest| rest.strip_prefix(kind))
+        .and_then(|rest| rest.strip_prefix(':'))
+    {
+        return id;
+    }
+
+    // URL
+    if let Some(path) = input
+        .strip_prefix("https://")
+        .or_else(|| input.strip_prefix("http://"))
+        .and_then(|rest| rest.strip_prefix("open.spotify.com/"))
+    {
+        // strip query parameters like '?si=...'
+        let path = path.split(['?', '#']).next().unwrap_or_default();
+        let mut segments = path.split('/');
+        // skip localized prefixes like 'intl-de'
+        while let Some(segment) = segments.next() {
+            if segment == kind {
+                return segments.next().unwrap_or_default();
+            }
+        }
+        return "";
+    }
+
+    // plain id
+    input
+}
+
+/// Parses a track id, uri or url
+pub fn parse_track_ref(input: &str) -> Option<TrackId<'static>> {
+    match extract_id("track", input) {
+        "" => None,
+        id => TrackId::from_id(id).ok(),
+    }
+    .map(TrackId::into_static)
+}
+
+/// Parses a playlist id, uri or url
+pub fn parse_playlist_ref(input: &str) -> Option<PlaylistId<'static>> {
+    match extract_id("playlist", input) {
+        "" => None,
+        id => PlaylistId::from_id(id).ok(),
+    }
+    .map(PlaylistId::into_static)
+}
+
+/// Parses an album id, uri or url
+pub fn parse_album_ref(input: &str) -> Option<AlbumId<'static>> {
+    match extract_id("album", input) {
+        "" => None,
+        id => AlbumId::from_id(id).ok(),
+    }
+    .map(AlbumId::into_static)
+}
+
+/// Parses an artist id, uri or url
+pub fn parse_artist_ref(input: &str) -> Option<ArtistId<'static>> {
+    match extract_id("artist", input) {
+        "" => None,
+        id => ArtistId::from_id(id).ok(),
+    }
+    .map(ArtistId::into_static)
+}
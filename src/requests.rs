@@ -0,0 +1,399 @@
+use futures::{stream, Stream, TryStreamExt};
+use rand::{thread_rng, Rng};
+use reqwest::StatusCode;
+use rspotify::{http::HttpError, model::Page, ClientError, ClientResult};
+use std::{
+    future::Future,
+    sync::{
+        atomic::{AtomicU32, Ordering},
+        Mutex, OnceLock,
+    },
+    time::Duration,
+};
+use tokio::{
+    sync::Semaphore,
+    time::{sleep_until, Instant},
+};
+
+/// Maximum amount of items per page, given by the spotify API docs
+const PAGE_SIZE: u32 = 50;
+
+/// How requests to Spotify are retried and limited
+#[derive(Debug, Clone)]
+pub struct RetryPolicy {
+    /// Retries of a single request
+    pub retries: u32,
+    /// Retries of all requests of the run together
+    pub budget: u32,
+    /// Requests running at the same time
+    pub max_concurrency: usize,
+    /// Delay before the first retry, doubled with every further one
+    pub base_delay: Duration,
+    pub max_delay: Duration,
+    /// Longer waits asked for by Spotify aren't waited for
+    pub max_retry_after: Duration,
+}
+
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        Self {
+            retries: 5,
+            budget: 50,
+            max_concurrency: 4,
+            base_delay: Duration::from_millis(500),
+            max_delay: Duration::from_secs(30),
+            max_retry_after: Duration::from_secs(120),
+        }
+    }
+}
+
+/// What to do about a failed request
+#[derive(Debug, PartialEq)]
+enum Failure {
+    /// Too many requests, wait as long as Spotify asks for if it does
+    RateLimited(Option<Duration>),
+    /// Network errors and server errors, worth retrying
+    Transient,
+    /// Authentification and other client errors, retrying won't help
+    Fatal,
+}
+
+/// Classifies the error of a request. Requests that aren't `repeatable` aren't retried on transport
+/// errors, as they may have reached Spotify before failing, unless the connection couldn't be made
+fn classify(error: &ClientError, repeatable: bool) -> Failure {
+    match error {
+        ClientError::Http(http_error) => match http_error.as_ref() {
+            HttpError::StatusCode(response) => match response.status() {
+                StatusCode::TOO_MANY_REQUESTS => Failure::RateLimited(
+                    response
+                        .headers()
+                        .get("Retry-After")
+                        .and_then(|value| value.to_str().ok())
+                        .and_then(|value| value.trim().parse().ok())
+                        .map(Duration::from_secs),
+                ),
+                status if status.is_server_error() => Failure::Transient,
+                // e.g. 401/403, the token or scopes are wrong
+                _ => Failure::Fatal,
+            },
+            HttpError::Client(e) if repeatable || e.is_connect() => Failure::Transient,
+            HttpError::Client(_) => Failure::Fatal,
+        },
+        ClientError::Io(_) => Failure::Transient,
+        _ => Failure::Fatal,
+    }
+}
+
+/// The state shared by all requests of the run
+struct Limiter {
+    policy: RetryPolicy,
+    permits: Semaphore,
+    budget: AtomicU32,
+    /// Set when rate limited, no request starts before
+    paused_until: Mutex<Option<Instant>>,
+}
+
+impl Limiter {
+    fn new(policy: RetryPolicy) -> Self {
+        Self {
+            permits: Semaphore::new(policy.max_concurrency.max(1)),
+            budget: AtomicU32::new(policy.budget),
+            paused_until: Mutex::new(None),
+            policy,
+        }
+    }
+
+    /// Takes a retry from the budget, `false` if it's used up
+    fn take_retry(&self) -> bool {
+        self.budget
+            .fetch_update(Ordering::SeqCst, Ordering::SeqCst, |budget| {
+                budget.checked_sub(1)
+            })
+            .is_ok()
+    }
+
+    /// Exponential backoff with jitter: a random delay between half and all of `base * 2^retry`
+    fn backoff(&self, retry: u32) -> Duration {
+        let delay = self
+            .policy
+            .base_delay
+            .saturating_mul(2u32.saturating_pow(retry))
+            .min(self.policy.max_delay);
+        delay.mul_f64(thread_rng().gen_range(0.5..=1.0))
+    }
+
+    fn pause_until(&self, until: Instant) {
+        let mut paused_until = self.paused_until.lock().expect("lock isn't poisoned");
+        if paused_until.is_none_or(|paused_until| paused_until < until) {
+            *paused_until = Some(until);
+        }
+    }
+
+    /// Waits until no pause is active, including pauses started while waiting
+    async fn wait_for_pause(&self) {
+        loop {
+            let paused_until = *self.paused_until.lock().expect("lock isn't poisoned");
+            match paused_until {
+                Some(paused_until) if paused_until > Instant::now() => {
+                    sleep_until(paused_until).await
+                }
+                _ => return,
+            }
+        }
+    }
+
+    async fn request<T, F: Future<Output = ClientResult<T>>>(
+        &self,
+        mut request: impl FnMut() -> F,
+        repeatable: bool,
+    ) -> ClientResult<T> {
+        let mut retry = 0;
+        loop {
+            let result = {
+                let _permit = self
+                    .permits
+                    .acquire()
+                    .await
+                    .expect("semaphore isn't closed");
+                // checked with the permit, so requests queued on it don't run into a pause
+                self.wait_for_pause().await;
+                request().await
+            };
+            let error = match result {
+                Ok(result) => return Ok(result),
+                Err(error) => error,
+            };
+
+            let delay = match classify(&error, repeatable) {
+                Failure::Fatal => return Err(error),
+                Failure::RateLimited(Some(retry_after))
+                    if retry_after > self.policy.max_retry_after =>
+                {
+                    println!("Rate limited for {}s, not waiting", retry_after.as_secs());
+                    return Err(error);
+                }
+                Failure::RateLimited(retry_after) => {
+                    let delay = retry_after.unwrap_or_else(|| self.backoff(retry));
+                    // the limit is per app, so all requests wait
+                    self.pause_until(Instant::now() + delay);
+                    delay
+                }
+                Failure::Transient => self.backoff(retry),
+            };
+            if retry >= self.policy.retries {
+                return Err(error);
+            }
+            if !self.take_retry() {
+                println!("Retry budget used up");
+                return Err(error);
+            }
+
+            println!(
+                "Retrying in {:.1}s due to error: {error}",
+                delay.as_secs_f64()
+            );
+            retry += 1;
+            sleep_until(Instant::now() + delay).await;
+        }
+    }
+}
+
+static LIMITER: OnceLock<Limiter> = OnceLock::new();
+
+fn limiter() -> &'static Limiter {
+    LIMITER.get_or_init(|| Limiter::new(RetryPolicy::default()))
+}
+
+/// Sets the policy of all following requests, only has an effect before the first request
+pub fn configure(policy: RetryPolicy) {
+    let _ = LIMITER.set(Limiter::new(policy));
+}
+
+/// Runs the Spotify request with the shared policy:
+/// limited concurrency, retries with backoff and waiting as long as Spotify asks for when rate limited
+pub async fn request<T, F: Future<Output = ClientResult<T>>>(
+    request: impl FnMut() -> F,
+) -> ClientResult<T> {
+    limiter().request(request, true).await
+}
+
+/// Runs the Spotify request with the shared policy, for requests that can't be repeated safely
+/// like adding or creating items: transport errors are only retried if the request can't have been sent
+pub async fn request_once<T, F: Future<Output = ClientResult<T>>>(
+    request: impl FnMut() -> F,
+) -> ClientResult<T> {
+    limiter().request(request, false).await
+}
+
+/// The items of all pages of a paginated request, each page requested with the shared policy.
+/// The request gets the limit and offset of the page
+pub fn paginate<'a, T: 'a, F: Future<Output = ClientResult<Page<T>>> + 'a>(
+    page: impl FnMut(u32, u32) -> F + 'a,
+) -> impl Stream<Item = ClientResult<T>> + 'a {
+    stream::try_unfold((page, Some(0)), |(mut page, offset)| async move {
+        let Some(offset) = offset else {
+            return Ok::<_, ClientError>(None);
+        };
+        let items = request(|| page(PAGE_SIZE, offset)).await?;
+        let next = items.next.is_some().then_some(offset + PAGE_SIZE);
+        Ok(Some((
+            stream::iter(items.items.into_iter().map(Ok)),
+            (page, next),
+        )))
+    })
+    .try_flatten()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::{
+        cell::{Cell, RefCell},
+        io,
+    };
+
+    fn status_error(status: u16, retry_after: Option<&str>) -> ClientError {
+        let mut response = http::Response::builder().status(status);
+        if let Some(retry_after) = retry_after {
+            response = response.header("Retry-After", retry_after);
+        }
+        HttpError::StatusCode(response.body("").unwrap().into()).into()
+    }
+
+    /// Runs a request failing with the errors first, returns the result and the amount of attempts
+    async fn run(limiter: &Limiter, errors: Vec<ClientError>) -> (ClientResult<()>, usize) {
+        let errors = RefCell::new(errors.into_iter());
+        let attempts = Cell::new(0);
+        let result = limiter
+            .request(
+                || {
+                    attempts.set(attempts.get() + 1);
+                    let error = errors.borrow_mut().next();
+                    async move { error.map_or(Ok(()), Err) }
+                },
+                true,
+            )
+            .await;
+        (result, attempts.get())
+    }
+
+    fn classify_repeatable(error: &ClientError) -> Failure {
+        classify(error, true)
+    }
+
+    #[test]
+    fn classifies_errors() {
+        assert_eq!(
+            classify_repeatable(&status_error(429, Some("7"))),
+            Failure::RateLimited(Some(Duration::from_secs(7)))
+        );
+        assert_eq!(
+            classify_repeatable(&status_error(429, None)),
+            Failure::RateLimited(None)
+        );
+        assert_eq!(
+            classify_repeatable(&status_error(503, None)),
+            Failure::Transient
+        );
+        assert_eq!(
+            classify_repeatable(&status_error(401, None)),
+            Failure::Fatal
+        );
+        assert_eq!(
+            classify_repeatable(&status_error(403, None)),
+            Failure::Fatal
+        );
+        assert_eq!(
+            classify_repeatable(&io::Error::other("connection reset").into()),
+            Failure::Transient
+        );
+
+        let transport_error: ClientError =
+            HttpError::from(reqwest::Client::new().get("not a url").build().unwrap_err()).into();
+        assert_eq!(classify(&transport_error, true), Failure::Transient);
+        assert_eq!(classify(&transport_error, false), Failure::Fatal);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn waits_as_long_as_asked_when_rate_limited() {
+        let limiter = Limiter::new(RetryPolicy::default());
+        let start = Instant::now();
+
+        let (result, attempts) = run(&limiter, vec![status_error(429, Some("7"))]).await;
+
+        assert!(result.is_ok());
+        assert_eq!(attempts, 2);
+        assert_eq!(start.elapsed().as_secs(), 7);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn pauses_queued_requests_when_rate_limited() {
+        let limiter = Limiter::new(RetryPolicy {
+            max_concurrency: 1,
+            ..RetryPolicy::default()
+        });
+        let start = Instant::now();
+        let queued_started = Cell::new(None);
+
+        let limited_attempts = Cell::new(0);
+        // rate limited after a second, while the other request waits for the permit
+        let limited = limiter.request(
+            || {
+                limited_attempts.set(limited_attempts.get() + 1);
+                let first = limited_attempts.get() == 1;
+                async move {
+                    if first {
+                        tokio::time::sleep(Duration::from_secs(1)).await;
+                        return Err(status_error(429, Some("7")));
+                    }
+                    Ok(())
+                }
+            },
+            true,
+        );
+        let queued = limiter.request(
+            || {
+                queued_started.set(Some(start.elapsed()));
+                async { Ok(()) }
+            },
+            true,
+        );
+        let (limited, queued) = futures::join!(limited, queued);
+
+        assert!(limited.is_ok() && queued.is_ok());
+        assert!(queued_started.get().unwrap() >= Duration::from_secs(8));
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn fails_fast_on_fatal_errors() {
+        let limiter = Limiter::new(RetryPolicy::default());
+
+        let (result, attempts) = run(&limiter, vec![status_error(403, None)]).await;
+
+        assert!(result.is_err());
+        assert_eq!(attempts, 1);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn backs_off_within_the_budgets() {
+        let limiter = Limiter::new(RetryPolicy {
+            retries: 3,
+            budget: 5,
+            ..RetryPolicy::default()
+        });
+        let failures = || (0..10).map(|_| status_error(500, None)).collect();
+
+        let start = Instant::now();
+        let (result, attempts) = run(&limiter, failures()).await;
+        assert!(result.is_err());
+        assert_eq!(attempts, 4);
+        // half to all of 0.5s + 1s + 2s
+        let elapsed = start.elapsed();
+        assert!(elapsed >= Duration::from_millis(1750) && elapsed <= Duration::from_millis(3500));
+
+        // only 2 retries of the budget are left
+        let (result, attempts) = run(&limiter, failures()).await;
+        assert!(result.is_err());
+        assert_eq!(attempts, 3);
+    }
+}
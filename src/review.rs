@@ -0,0 +1,282 @@
+use crate::{
+    db::{load_hashmap, make_readable, parse_rating, TimeRating},
+    journal::save_with_journal,
+    requests,
+};
+use crossterm::{
+    cursor::{Hide, MoveTo, Show},
+    event::{read, Event, KeyCode, KeyEvent, KeyEventKind, KeyModifiers},
+    execute, queue,
+    style::Print,
+    terminal::{
+        disable_raw_mode, enable_raw_mode, Clear, ClearType, EnterAlternateScreen,
+        LeaveAlternateScreen,
+    },
+};
+use rspotify::{
+    model::{FullTrack, TrackId},
+    prelude::*,
+    AuthCodeSpotify,
+};
+use std::{
+    collections::HashMap,
+    error::Error,
+    io::{stdout, Write},
+};
+
+/// Walks through all unrated songs in a full-screen terminal session.
+/// If `dry_run` is set, nothing is saved or played and the changes are printed at the end
+pub async fn review(
+    spotify: AuthCodeSpotify,
+    db_path: String,
+    newest_first: bool,
+    autoplay: bool,
+    dry_run: bool,
+) -> Result<(), Box<dyn Error>> {
+    // get ratings db
+    let ratings = match load_hashmap(db_path.clone()) {
+        Ok(hashmap) => hashmap,
+        Err(e) => {
+            println!("Error loading database: {e}");
+            return Ok(());
+        }
+    };
+
+    // get unrated songs, sorted by time added
+    let mut unrated: Vec<_> = ratings
+        .iter()
+        .filter(|(_, time_rating)| time_rating.is_unrated())
+        .map(|(song_id, time_rating)| (song_id.clone(), time_rating.added_at))
+        .collect();
+    unrated.sort_by_key(|(_, added_at)| *added_at);
+    if newest_first {
+        unrated.reverse();
+    }
+    let song_ids: Vec<_> = unrated.into_iter().map(|(song_id, _)| song_id).collect();
+
+    if song_ids.is_empty() {
+        println!("No unrated songs");
+        return Ok(());
+    }
+
+    let original = ratings.clone();
+    let mut session = Session {
+        spotify,
+        db_path,
+        dry_run,
+        ratings,
+        song_ids,
+        tracks: HashMap::new(),
+        position: 0,
+        rated: 0,
+        status: String::new(),
+    };
+
+    enable_raw_mode()?;
+    execute!(stdout(), EnterAlternateScreen, Hide)?;
+
+    let result = session.run(autoplay).await;
+
+    // always restore the terminal, even if the session failed
+    execute!(stdout(), Show, LeaveAlternateScreen)?;
+    disable_raw_mode()?;
+
+    result?;
+    println!("Rated {} songs", session.rated);
+    if dry_run {
+        save_with_journal(session.db_path, "review", &original, &session.ratings, true)?;
+    }
+
+    Ok(())
+}
+
+struct Session {
+    spotify: AuthCodeSpotify,
+    db_path: String,
+    /// Keeps the ratings in memory and doesn't start playback
+    dry_run: bool,
+    ratings: HashMap<String, TimeRating>,
+    /// The unrated songs, in review order
+    song_ids: Vec<String>,
+    /// Cache of the fetched song metadata
+    tracks: HashMap<String, FullTrack>,
+    position: usize,
+    rated: usize,
+    /// Message shown below the song, e.g. the last rating
+    status: String,
+}
+
+impl Session {
+    async fn run(&mut self, autoplay: bool) -> Result<(), Box<dyn Error>> {
+        let mut played_position = None;
+
+        while self.position < self.song_ids.len() {
+            let song_id = self.song_ids[self.position].clone();
+
+            // fetch metadata
+            if !self.tracks.contains_key(&song_id) {
+                let track_id = TrackId::from_id(song_id.as_str())?;
+                match requests::request(|| self.spotify.track(track_id.clone(), None)).await {
+                    Ok(track) => {
+                        self.tracks.insert(song_id.clone(), track);
+                    }
+                    Err(e) => self.status = format!("Error fetching song: {e}"),
+                }
+            }
+
+            if autoplay && played_position != Some(self.position) {
+                self.play(&song_id).await;
+                played_position = Some(self.position);
+            }
+
+            self.draw(&song_id)?;
+
+            let Event::Key(KeyEvent {
+                code,
+                modifiers,
+                kind: KeyEventKind::Press,
+                ..
+            }) = read()?
+            else {
+                continue;
+            };
+
+            match code {
+                KeyCode::Char(key @ '1'..='4') => {
+                    let rating = parse_rating(&key.to_string()).expect("1-4 are valid ratings");
+                    self.rate(&song_id, rating)?;
+                    self.position += 1;
+                }
+                KeyCode::Char('s') | KeyCode::Right | KeyCode::Down => {
+                    self.status = format!("Skipped {}", self.name(&song_id));
+                    self.position += 1;
+                }
+                KeyCode::Char('b') | KeyCode::Left | KeyCode::Up => {
+                    self.status.clear();
+                    self.position = self.position.saturating_sub(1);
+                }
+                KeyCode::Char('p') => self.play(&song_id).await,
+                KeyCode::Char('c') if modifiers.contains(KeyModifiers::CONTROL) => break,
+                KeyCode::Char('q') | KeyCode::Esc => break,
+                _ => {}
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Rates the song and saves the database immediately
+    fn rate(&mut self, song_id: &str, rating: f32) -> Result<(), Box<dyn Error>> {
+        let before = self.ratings.clone();
+        self.ratings
+            .get_mut(song_id)
+            .expect("reviewed songs are in the database")
+            .rate(rating);
+        // the changes of a dry run are printed once the terminal is restored
+        if !self.dry_run {
+            save_with_journal(
+                self.db_path.clone(),
+                "review",
+                &before,
+                &self.ratings,
+                false,
+            )?;
+        }
+
+        self.rated += 1;
+        self.status = format!("Rated {} as {}", self.name(song_id), make_readable(rating));
+
+        Ok(())
+    }
+
+    /// Starts playback of the song on the active device
+    async fn play(&mut self, song_id: &str) {
+        if self.dry_run {
+            self.status = "Dry run, not starting playback".to_string();
+            return;
+        }
+        let Ok(track_id) = TrackId::from_id(song_id) else {
+            return;
+        };
+        if let Err(e) = requests::request(|| {
+            self.spotify.start_uris_playback(
+                [PlayableId::Track(track_id.clone())],
+                None,
+                None,
+                None,
+            )
+        })
+        .await
+        {
+            self.status = format!("Error starting playback: {e}");
+        }
+    }
+
+    fn name(&self, song_id: &str) -> String {
+        self.tracks
+            .get(song_id)
+            .map_or_else(|| song_id.to_string(), |track| track.name.clone())
+    }
+
+    fn draw(&self, song_id: &str) -> Result<(), Box<dyn Error>> {
+        let time_rating = &self.ratings[song_id];
+
+        let mut lines = vec![
+            format!(
+                "Reviewing unrated songs ({}/{})",
+                self.position + 1,
+                self.song_ids.len()
+            ),
+            String::new(),
+        ];
+        match self.tracks.get(song_id) {
+            Some(track) => {
+                lines.push(format!("Title:   {}", track.name));
+                lines.push(format!(
+                    "Artists: {}",
+                    track
+                        .artists
+                        .iter()
+                        .map(|artist| artist.name.as_str())
+                        .collect::<Vec<_>>()
+                        .join(", ")
+                ));
+                lines.push(format!("Album:   {}", track.album.name));
+            }
+            None => lines.push(format!("Song:    {song_id}")),
+        }
+        lines.push(format!(
+            "Added:   {}",
+            time_rating.added_at.map_or_else(
+                || "unknown".to_string(),
+                |added_at| added_at.format("%Y-%m-%d").to_string()
+            )
+        ));
+        lines.push(format!(
+            "Rating:  {}{}",
+            make_readable(time_rating.rating),
+            if time_rating.is_unrated() {
+                " (unrated)"
+            } else {
+                ""
+            }
+        ));
+        lines.push(String::new());
+        lines.push(
+            "[1] great  [2] good  [3] ok  [4] bad  [s] skip  [b] back  [p] play  [q] quit"
+                .to_string(),
+        );
+        lines.push(String::new());
+        lines.push(self.status.clone());
+
+        let mut stdout = stdout();
+        queue!(stdout, Clear(ClearType::All), MoveTo(0, 0))?;
+        for line in lines {
+            // raw mode needs explicit carriage returns
+            queue!(stdout, Print(line), Print("\r\n"))?;
+        }
+        stdout.flush()?;
+
+        Ok(())
+    }
+}
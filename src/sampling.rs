@@ -0,0 +1,222 @@
+use crate::{
+    db::make_readable,
+    diversity::DiversityRules,
+    weights::{display_name, WeightedSong},
+};
+use rand::{distributions::WeightedIndex, prelude::*, rngs::StdRng};
+use std::{collections::HashMap, error::Error};
+
+/// Amount of songs/artists listed per section of the simulation report
+const REPORT_LENGTH: usize = 10;
+
+/// A seeded rng if a seed is given, so samples can be reproduced
+pub fn rng(seed: Option<u64>) -> StdRng {
+    match seed {
+        Some(seed) => StdRng::seed_from_u64(seed),
+        None => StdRng::from_entropy(),
+    }
+}
+
+/// Draws `amount` songs by weight without replacement, in the order they were drawn.
+/// Songs without weight are never drawn
+pub fn sample_without_replacement<'a>(
+    weighted_songs: &'a [WeightedSong],
+    amount: usize,
+    rng: &mut impl Rng,
+) -> Vec<&'a WeightedSong> {
+    // Efraimidis-Spirakis: sorting by u^(1/weight) yields a weighted random permutation
+    let mut keyed: Vec<_> = weighted_songs
+        .iter()
+        .filter(|weighted_song| weighted_song.weight > 0.)
+        .map(|weighted_song| (rng.gen::<f64>().ln() / weighted_song.weight, weighted_song))
+        .collect();
+    keyed.sort_by(|a, b| b.0.total_cmp(&a.0));
+
+    keyed
+        .into_iter()
+        .take(amount)
+        .map(|(_, weighted_song)| weighted_song)
+        .collect()
+}
+
+/// Draws `amount` songs by weight, where songs can repeat,
+/// but not within `window` draws of each other or of the `recent` songs (oldest first).
+/// The artist spacing is relaxed if no song fits it, the album limit never is
+pub fn sample_with_window<'a>(
+    weighted_songs: &'a [WeightedSong],
+    amount: usize,
+    window: usize,
+    recent: &[String],
+    rules: &DiversityRules,
+    rng: &mut impl Rng,
+) -> Vec<&'a WeightedSong> {
+    let mut history: Vec<&str> = recent.iter().map(String::as_str).collect();
+    let mut placed = Vec::with_capacity(amount);
+    let mut album_counts = HashMap::new();
+    let mut samples = Vec::with_capacity(amount);
+
+    for _ in 0..amount {
+        let blocked = &history[history.len().saturating_sub(window)..];
+        let distribution = |spaced: bool| {
+            WeightedIndex::new(weighted_songs.iter().map(|weighted_song| {
+                let metadata = weighted_song.time_rating.metadata.as_ref();
+                if blocked.contains(&weighted_song.song_id.as_str())
+                    || !rules.album_allows(&album_counts, metadata)
+                    || (spaced && !rules.spacing_allows(&placed, metadata))
+                {
+                    0.
+                } else {
+                    weighted_song.weight
+                }
+            }))
+        };
+        let Ok(distribution) = distribution(true).or_else(|_| distribution(false)) else {
+            // every song with weight is blocked
+            break;
+        };
+
+        let weighted_song = &weighted_songs[distribution.sample(rng)];
+        let metadata = weighted_song.time_rating.metadata.as_ref();
+        if let Some(album_id) = metadata.and_then(|metadata| metadata.album_id.clone()) {
+            *album_counts.entry(album_id).or_default() += 1;
+        }
+        history.push(&weighted_song.song_id);
+        placed.push(metadata);
+        samples.push(weighted_song);
+    }
+
+    samples
+}
+
+/// Expected and simulated plays of a group of songs
+#[derive(Default)]
+struct Plays {
+    songs: usize,
+    expected: f64,
+    simulated: u32,
+}
+
+/// Simulates `plays` weighted random plays (with repetition, like a shuffling client)
+/// and reports the plays per rating, per artist and of the top and bottom songs
+pub fn simulate(
+    weighted_songs: &[WeightedSong],
+    plays: u32,
+    seed: Option<u64>,
+) -> Result<(), Box<dyn Error>> {
+    let distribution = WeightedIndex::new(
+        weighted_songs
+            .iter()
+            .map(|weighted_song| weighted_song.weight),
+    )?;
+    let total_weight: f64 = weighted_songs
+        .iter()
+        .map(|weighted_song| weighted_song.weight)
+        .sum();
+
+    let mut rng = rng(seed);
+    let mut simulated = vec![0; weighted_songs.len()];
+    for _ in 0..plays {
+        simulated[distribution.sample(&mut rng)] += 1;
+    }
+
+    // expected and simulated plays of each song
+    let songs: Vec<_> = weighted_songs
+        .iter()
+        .zip(simulated)
+        .map(|(weighted_song, simulated)| {
+            (
+                weighted_song,
+                plays as f64 * weighted_song.weight / total_weight,
+                simulated,
+            )
+        })
+        .collect();
+
+    println!("Simulated {plays} plays of {} songs", songs.len());
+
+    // per rating
+    let mut ratings: Vec<(f32, Plays)> = Vec::new();
+    for (weighted_song, expected, simulated) in &songs {
+        let rating = weighted_song.time_rating.rating;
+        let index = match ratings.iter().position(|(other, _)| *other == rating) {
+            Some(index) => index,
+            None => {
+                ratings.push((rating, Plays::default()));
+                ratings.len() - 1
+            }
+        };
+        let bucket = &mut ratings[index].1;
+        bucket.songs += 1;
+        bucket.expected += expected;
+        bucket.simulated += simulated;
+    }
+    ratings.sort_by(|a, b| a.0.total_cmp(&b.0));
+    println!();
+    println!("Per rating:");
+    for (rating, bucket) in &ratings {
+        print_plays(&make_readable(*rating), bucket, plays);
+    }
+
+    // per artist, songs without metadata are grouped as unknown
+    let mut artists: HashMap<&str, Plays> = HashMap::new();
+    for (weighted_song, expected, simulated) in &songs {
+        let song_artists = match &weighted_song.time_rating.metadata {
+            Some(metadata) => metadata.artists.iter().map(String::as_str).collect(),
+            None => vec!["(unknown)"],
+        };
+        for artist in song_artists {
+            let bucket = artists.entry(artist).or_default();
+            bucket.songs += 1;
+            bucket.expected += expected;
+            bucket.simulated += simulated;
+        }
+    }
+    let mut artists: Vec<_> = artists.into_iter().collect();
+    artists.sort_by(|a, b| b.1.expected.total_cmp(&a.1.expected));
+    println!();
+    println!("Top artists:");
+    for (artist, bucket) in artists.iter().take(REPORT_LENGTH) {
+        print_plays(artist, bucket, plays);
+    }
+
+    // top and bottom songs
+    let mut songs = songs;
+    songs.sort_by(|a, b| b.1.total_cmp(&a.1));
+    println!();
+    println!("Top songs:");
+    for (weighted_song, expected, simulated) in songs.iter().take(REPORT_LENGTH) {
+        print_song(weighted_song, *expected, *simulated);
+    }
+    // the top songs already list all songs of small databases
+    if songs.len() > REPORT_LENGTH {
+        println!();
+        println!("Bottom songs:");
+        for (weighted_song, expected, simulated) in songs
+            .iter()
+            .skip(songs.len().saturating_sub(REPORT_LENGTH).max(REPORT_LENGTH))
+        {
+            print_song(weighted_song, *expected, *simulated);
+        }
+    }
+
+    Ok(())
+}
+
+fn print_plays(name: &str, bucket: &Plays, plays: u32) {
+    println!(
+        "  {name}: {} songs, {:.1} expected plays ({:.1}%), {} simulated",
+        bucket.songs,
+        bucket.expected,
+        100. * bucket.expected / plays as f64,
+        bucket.simulated
+    );
+}
+
+fn print_song(weighted_song: &WeightedSong, expected: f64, simulated: u32) {
+    println!(
+        "  {} ({}): weight {:.2}, {expected:.2} expected plays, {simulated} simulated",
+        display_name(weighted_song),
+        make_readable(weighted_song.time_rating.rating),
+        weighted_song.weight,
+    );
+}
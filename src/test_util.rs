@@ -0,0 +1,13 @@
+use rspotify::model::{PlaylistId, TrackId};
+use std::ops::Range;
+
+/// Valid track ids made from the numbers, so fake clients can map them back to their index
+pub fn song_ids(range: Range<usize>) -> Vec<TrackId<'static>> {
+    range
+        .map(|i| TrackId::from_id(format!("{i:0>22}")).unwrap())
+        .collect()
+}
+
+pub fn playlist_id() -> PlaylistId<'static> {
+    PlaylistId::from_id("37i9dQZF1DXcBWIGoYBM5M").unwrap()
+}
@@ -0,0 +1,727 @@
+use crate::{
+    compare,
+    db::{make_readable, TimeRating},
+    lists::{Listed, Lists},
+};
+use chrono::{DateTime, Utc};
+use serde::Serialize;
+use std::{
+    cmp::Reverse,
+    collections::{HashMap, HashSet},
+};
+
+/// Names of the built-in strategies, as accepted by `strategy_from_name`
+pub const STRATEGY_NAMES: [&str; 4] = [
+    "linear-rank",
+    "proportional",
+    "exponential",
+    "rank-with-ties",
+];
+
+/// Names of the output formats, as accepted by `format_weights`
+pub const FORMAT_NAMES: [&str; 5] = ["plugin", "json", "csv", "tsv", "explain"];
+
+/// Range of the generated weights
+#[derive(Debug, Clone, Copy)]
+pub struct WeightRange {
+    pub min: f64,
+    pub max: f64,
+}
+
+/// Turns the ratings of the songs into weights
+pub trait WeightStrategy {
+    /// `ratings` are sorted from best to worst (1 = great, 4 = bad),
+    /// the weights are returned in the same order
+    fn weights(&self, ratings: &[f32]) -> Vec<f64>;
+}
+
+/// Weights decrease linearly with the rank from the maximum to the minimum,
+/// so songs with the same rating get different weights
+pub struct LinearRank {
+    pub range: WeightRange,
+}
+
+/// Weight difference between neighbouring ranks, so the last rank gets the minimum
+fn rank_step(range: WeightRange, len: usize) -> f64 {
+    if len <= 1 {
+        return 0.;
+    }
+    (range.max - range.min) / (len - 1) as f64
+}
+
+impl WeightStrategy for LinearRank {
+    fn weights(&self, ratings: &[f32]) -> Vec<f64> {
+        let step = rank_step(self.range, ratings.len());
+
+        (0..ratings.len())
+            .map(|i| self.range.max - step * i as f64)
+            .collect()
+    }
+}
+
+/// Weights are proportional to the rating, the best rating gets the maximum, the worst the minimum
+pub struct Proportional {
+    pub range: WeightRange,
+}
+
+impl WeightStrategy for Proportional {
+    fn weights(&self, ratings: &[f32]) -> Vec<f64> {
+        let (Some(&best), Some(&worst)) = (ratings.first(), ratings.last()) else {
+            return Vec::new();
+        };
+        let span = (worst - best) as f64;
+
+        // all songs are rated the same
+        if span == 0. {
+            return vec![self.range.max; ratings.len()];
+        }
+
+        ratings
+            .iter()
+            .map(|&rating| {
+                self.range.max - (self.range.max - self.range.min) * (rating - best) as f64 / span
+            })
+            .collect()
+    }
+}
+
+/// Weights fall by a factor of `base` for every rating step below the best rating,
+/// from the maximum towards (but never reaching) the minimum
+pub struct Exponential {
+    pub range: WeightRange,
+    pub base: f64,
+}
+
+impl WeightStrategy for Exponential {
+    fn weights(&self, ratings: &[f32]) -> Vec<f64> {
+        let Some(&best) = ratings.first() else {
+            return Vec::new();
+        };
+
+        ratings
+            .iter()
+            .map(|&rating| {
+                self.range.min
+                    + (self.range.max - self.range.min) * self.base.powf(-((rating - best) as f64))
+            })
+            .collect()
+    }
+}
+
+/// Like `LinearRank`, but songs with the same rating share the average of their ranks,
+/// so the maximum and minimum are only reached by songs that aren't tied
+pub struct RankWithTies {
+    pub range: WeightRange,
+}
+
+impl WeightStrategy for RankWithTies {
+    fn weights(&self, ratings: &[f32]) -> Vec<f64> {
+        let step = rank_step(self.range, ratings.len());
+
+        let mut weights = Vec::with_capacity(ratings.len());
+        for tied in ratings.chunk_by(|a, b| a == b) {
+            let average_rank = weights.len() as f64 + (tied.len() - 1) as f64 / 2.;
+            weights.extend(tied.iter().map(|_| self.range.max - step * average_rank));
+        }
+
+        weights
+    }
+}
+
+/// Creates one of the built-in strategies, `base` is only used by the exponential strategy
+pub fn strategy_from_name(name: &str, range: WeightRange, base: f64) -> Box<dyn WeightStrategy> {
+    match name {
+        "linear-rank" => Box::new(LinearRank { range }),
+        "proportional" => Box::new(Proportional { range }),
+        "exponential" => Box::new(Exponential { range, base }),
+        "rank-with-ties" => Box::new(RankWithTies { range }),
+        other => unreachable!("unknown strategy '{other}'"), // All strategies listed
+    }
+}
+
+/// Optional time based factors, multiplied onto the weight of the strategy
+#[derive(Debug, Clone, Copy)]
+pub struct TimeComponents {
+    /// Half-life in days of the boost of newly added songs, disabled if `None`
+    pub recency_half_life: Option<f64>,
+    /// Boost of a song added just now, e.g. 1 doubles its weight
+    pub recency_boost: f64,
+    /// Half-life in days of the weight of songs that were not rated in a while, disabled if `None`
+    pub staleness_half_life: Option<f64>,
+    /// Factor stale songs fade towards
+    pub staleness_floor: f64,
+}
+
+impl TimeComponents {
+    /// Boost that decays with the time since the song was added, songs added at an unknown time get none
+    pub fn recency(&self, time_rating: &TimeRating, now: DateTime<Utc>) -> f64 {
+        match (self.recency_half_life, time_rating.added_at) {
+            (Some(half_life), Some(added_at)) => {
+                1. + self.recency_boost * decay(days_since(added_at, now), half_life)
+            }
+            _ => 1.,
+        }
+    }
+
+    /// Fades songs that were not rated in a while, songs that were never rated count from when they were added
+    pub fn staleness(&self, time_rating: &TimeRating, now: DateTime<Utc>) -> f64 {
+        match (
+            self.staleness_half_life,
+            time_rating.rated_at.or(time_rating.added_at),
+        ) {
+            (Some(half_life), Some(rated_at)) => {
+                self.staleness_floor
+                    + (1. - self.staleness_floor) * decay(days_since(rated_at, now), half_life)
+            }
+            _ => 1.,
+        }
+    }
+
+    /// The formulas of the enabled components
+    pub fn formula(&self) -> Vec<String> {
+        let mut formula = Vec::new();
+        if let Some(half_life) = self.recency_half_life {
+            formula.push(format!(
+                "recency = 1 + {} * 0.5^(days since added / {half_life})",
+                self.recency_boost
+            ));
+        }
+        if let Some(half_life) = self.staleness_half_life {
+            formula.push(format!(
+                "staleness = {0} + (1 - {0}) * 0.5^(days since rated / {half_life})",
+                self.staleness_floor
+            ));
+        }
+
+        formula
+    }
+}
+
+fn days_since(time: DateTime<Utc>, now: DateTime<Utc>) -> f64 {
+    (now - time).num_seconds().max(0) as f64 / (24. * 60. * 60.)
+}
+
+/// Halves every `half_life`
+fn decay(age: f64, half_life: f64) -> f64 {
+    0.5f64.powf(age / half_life)
+}
+
+/// A song with its weight and the components it was built from
+#[derive(Debug, Clone)]
+pub struct WeightedSong {
+    pub song_id: String,
+    pub time_rating: TimeRating,
+    /// Position among all songs, best first
+    pub rank: usize,
+    /// Position among the songs with the same rating
+    pub tie_rank: usize,
+    /// Amount of songs with the same rating
+    pub ties: usize,
+    /// Weight given by the strategy
+    pub base: f64,
+    pub recency: f64,
+    pub staleness: f64,
+    /// Factor limiting the weight share of the song's artist
+    pub artist_cap: f64,
+    /// Whether the song is pinned or banned, overriding the weight
+    pub listed: Option<Listed>,
+    pub weight: f64,
+}
+
+/// Everything the weights are built with
+#[derive(Debug, Clone)]
+pub struct WeightConfig {
+    /// One of `STRATEGY_NAMES`
+    pub strategy: String,
+    pub range: WeightRange,
+    /// Only used by the exponential strategy
+    pub base: f64,
+    pub time_components: TimeComponents,
+    /// Orders the songs by their comparison scores instead of their ratings
+    pub by_elo: bool,
+    /// Rating context to use instead of the default one, falling back to the default rating
+    pub context: Option<String>,
+    /// Maximum share of the total weight a single artist may have, e.g. 0.1 for 10%
+    pub max_artist_share: Option<f64>,
+}
+
+impl WeightConfig {
+    /// Creates the weights of all songs, sorted from best to worst.
+    /// Banned songs get a weight of 0, pinned ones the highest weight
+    pub fn create_weights(
+        &self,
+        mut ratings: HashMap<String, TimeRating>,
+        lists: &Lists,
+        now: DateTime<Utc>,
+    ) -> Vec<WeightedSong> {
+        if let Some(context) = &self.context {
+            for time_rating in ratings.values_mut() {
+                time_rating.rating = time_rating.rating_in(Some(context));
+            }
+        }
+        // uncompared songs are seeded from the rating of the context.
+        // Not clamped, so songs beyond the rating scale keep the order of their scores
+        if self.by_elo {
+            for time_rating in ratings.values_mut() {
+                time_rating.rating =
+                    compare::unclamped_rating_from_score(compare::score(time_rating).score);
+            }
+        }
+
+        let strategy = strategy_from_name(&self.strategy, self.range, self.base);
+        let mut weighted_songs =
+            create_weights(ratings, strategy.as_ref(), &self.time_components, now);
+
+        // ban before capping, so banned songs don't count towards the artist shares
+        for weighted_song in &mut weighted_songs {
+            weighted_song.listed = lists.status(
+                &weighted_song.song_id,
+                weighted_song.time_rating.metadata.as_ref(),
+            );
+            if weighted_song.listed == Some(Listed::Banned) {
+                weighted_song.weight = 0.;
+            }
+        }
+
+        if let Some(max_artist_share) = self.max_artist_share {
+            cap_artist_shares(&mut weighted_songs, max_artist_share);
+        }
+
+        let highest = weighted_songs
+            .iter()
+            .map(|weighted_song| weighted_song.weight)
+            .fold(0., f64::max);
+        for weighted_song in &mut weighted_songs {
+            if weighted_song.listed == Some(Listed::Pinned) {
+                weighted_song.weight = highest;
+            }
+        }
+
+        weighted_songs
+    }
+
+    /// The formula the weights are built with
+    pub fn formula(&self, lists: &Lists) -> Vec<String> {
+        let mut formula = vec![format!(
+            "weight = {}({}..{}) * recency * staleness{}",
+            self.strategy,
+            self.range.min,
+            self.range.max,
+            if self.max_artist_share.is_some() {
+                " * artist cap"
+            } else {
+                ""
+            }
+        )];
+        formula.extend(
+            self.time_components
+                .formula()
+                .into_iter()
+                .map(|component| format!("  {component}")),
+        );
+        if let Some(max_artist_share) = self.max_artist_share {
+            formula.push(format!(
+                "  artist cap = scales the songs of each artist to at most {:.1}% of the total weight",
+                max_artist_share * 100.
+            ));
+        }
+        if let Some(context) = &self.context {
+            formula.push(format!(
+                "  ratings of the context '{context}', falling back to the default rating"
+            ));
+        }
+        if !lists.is_empty() {
+            formula.push(
+                "  pinned songs get the highest weight, banned songs a weight of 0".to_string(),
+            );
+        }
+
+        formula
+    }
+}
+
+/// Creates the weights of all songs, sorted from best to worst
+pub fn create_weights(
+    ratings: HashMap<String, TimeRating>,
+    strategy: &dyn WeightStrategy,
+    time_components: &TimeComponents,
+    now: DateTime<Utc>,
+) -> Vec<WeightedSong> {
+    let mut ratings_vec: Vec<_> = ratings.into_iter().collect();
+    // sort the vec by time added, then by rating (stable, so newer songs stay first)
+    ratings_vec.sort_by_key(|(_, time_rating)| Reverse(time_rating.added_at));
+    ratings_vec.sort_by(|a, b| {
+        a.1.rating
+            .partial_cmp(&b.1.rating)
+            .expect("all elements have a (non NaN) rating")
+    });
+
+    let ratings: Vec<_> = ratings_vec
+        .iter()
+        .map(|(_, time_rating)| time_rating.rating)
+        .collect();
+    let weights = strategy.weights(&ratings);
+    // position of each song among the songs with the same rating
+    let ties = ratings
+        .chunk_by(|a, b| a == b)
+        .flat_map(|tied| (0..tied.len()).map(move |tie_rank| (tie_rank, tied.len())));
+
+    ratings_vec
+        .into_iter()
+        .zip(weights)
+        .zip(ties)
+        .enumerate()
+        .map(
+            |(rank, (((song_id, time_rating), base), (tie_rank, ties)))| {
+                let recency = time_components.recency(&time_rating, now);
+                let staleness = time_components.staleness(&time_rating, now);
+                WeightedSong {
+                    song_id,
+                    time_rating,
+                    rank,
+                    tie_rank,
+                    ties,
+                    base,
+                    recency,
+                    staleness,
+                    artist_cap: 1.,
+                    listed: None,
+                    weight: base * recency * staleness,
+                }
+            },
+        )
+        .collect()
+}
+
+/// Parses a finite number of the time components
+fn parse_factor(input: &str) -> Result<f64, String> {
+    input
+        .parse::<f64>()
+        .ok()
+        .filter(|factor| factor.is_finite())
+        .ok_or_else(|| format!("expected a number, got '{input}'"))
+}
+
+/// Parses a half-life in days, which has to be positive
+pub fn parse_half_life(input: &str) -> Result<f64, String> {
+    let days = parse_factor(input)?;
+    if days > 0. {
+        Ok(days)
+    } else {
+        Err(format!("half-life must be larger than 0, got {days}"))
+    }
+}
+
+/// Parses the boost of newly added songs, which can't be negative
+pub fn parse_boost(input: &str) -> Result<f64, String> {
+    let boost = parse_factor(input)?;
+    if boost >= 0. {
+        Ok(boost)
+    } else {
+        Err(format!("boost can't be negative, got {boost}"))
+    }
+}
+
+/// Parses the factor stale songs fade towards, between 0 and 1 (both inclusive)
+pub fn parse_floor(input: &str) -> Result<f64, String> {
+    let floor = parse_factor(input)?;
+    if (0. ..=1.).contains(&floor) {
+        Ok(floor)
+    } else {
+        Err(format!("floor must be between 0 and 1, got {floor}"))
+    }
+}
+
+/// Parses the base of the exponential strategy, which has to be larger than 1 so weights fall
+pub fn parse_base(input: &str) -> Result<f64, String> {
+    let base: f64 = input
+        .parse()
+        .map_err(|_| format!("expected a number like 2, got '{input}'"))?;
+    if base > 1. && base.is_finite() {
+        Ok(base)
+    } else {
+        Err(format!("base must be larger than 1, got {base}"))
+    }
+}
+
+/// Parses a maximum artist share, a fraction between 0 and 1 (both exclusive)
+pub fn parse_share(input: &str) -> Result<f64, String> {
+    let share: f64 = input
+        .parse()
+        .map_err(|_| format!("expected a fraction like 0.1, got '{input}'"))?;
+    if share > 0. && share < 1. {
+        Ok(share)
+    } else {
+        Err(format!(
+            "share must be between 0 and 1 (exclusive), got {share}"
+        ))
+    }
+}
+
+/// Scales down the songs of artists exceeding the share of the total weight.
+/// Songs count towards their first artist, songs without metadata are never capped.
+/// If the share can't be met, e.g. 0.1 with only 5 artists and no uncapped songs,
+/// all artists get the same share instead
+fn cap_artist_shares(weighted_songs: &mut [WeightedSong], max_share: f64) {
+    let artists = weighted_songs
+        .iter()
+        .filter(|weighted_song| weighted_song.weight > 0.)
+        .filter_map(primary_artist)
+        .collect::<HashSet<_>>()
+        .len();
+    let uncapped = weighted_songs
+        .iter()
+        .any(|weighted_song| weighted_song.weight > 0. && primary_artist(weighted_song).is_none());
+    if artists == 0 {
+        return;
+    }
+    let max_share = if uncapped {
+        max_share
+    } else {
+        max_share.max(1. / artists as f64)
+    };
+
+    // scaling one artist down raises the share of all others, so repeat until all fit
+    for _ in 0..weighted_songs.len() {
+        let total: f64 = weighted_songs
+            .iter()
+            .map(|weighted_song| weighted_song.weight)
+            .sum();
+
+        let mut artist_weights: HashMap<&str, f64> = HashMap::new();
+        for weighted_song in weighted_songs.iter() {
+            if let Some(artist_id) = primary_artist(weighted_song) {
+                *artist_weights.entry(artist_id).or_default() += weighted_song.weight;
+            }
+        }
+
+        // factor bringing the artist down to exactly the maximum share
+        let factors: HashMap<String, f64> = artist_weights
+            .into_iter()
+            .filter(|(_, weight)| *weight > max_share * total * (1. + 1e-9))
+            .map(|(artist_id, weight)| {
+                (
+                    artist_id.to_string(),
+                    max_share * (total - weight) / ((1. - max_share) * weight),
+                )
+            })
+            .collect();
+        if factors.is_empty() {
+            break;
+        }
+
+        for weighted_song in weighted_songs.iter_mut() {
+            if let Some(factor) = primary_artist(weighted_song).and_then(|id| factors.get(id)) {
+                weighted_song.artist_cap *= factor;
+                weighted_song.weight *= factor;
+            }
+        }
+    }
+}
+
+fn primary_artist(weighted_song: &WeightedSong) -> Option<&str> {
+    weighted_song
+        .time_rating
+        .metadata
+        .as_ref()
+        .and_then(|metadata| metadata.artist_ids.first())
+        .map(String::as_str)
+}
+
+/// Name and artists of the song, or its id if the metadata is missing
+pub fn display_name(weighted_song: &WeightedSong) -> String {
+    match &weighted_song.time_rating.metadata {
+        Some(metadata) => format!("{} - {}", metadata.name, metadata.artists.join(", ")),
+        None => weighted_song.song_id.clone(),
+    }
+}
+
+/// Describes how the weight of the song came to be
+pub fn explain(weighted_song: &WeightedSong, total: usize) -> Vec<String> {
+    let rating = make_readable(weighted_song.time_rating.rating);
+
+    vec![
+        format!(
+            "Song:      {} ({})",
+            display_name(weighted_song),
+            weighted_song.song_id
+        ),
+        format!("Rating:    {rating}"),
+        format!(
+            "Rank:      {}/{total}, {}/{} among songs rated {rating}",
+            weighted_song.rank + 1,
+            weighted_song.tie_rank + 1,
+            weighted_song.ties
+        ),
+        format!("Base:      {:.2}", weighted_song.base),
+        format!("Recency:   x{:.2}", weighted_song.recency),
+        format!("Staleness: x{:.2}", weighted_song.staleness),
+        format!("Artist:    x{:.2}", weighted_song.artist_cap),
+        format!(
+            "Listed:    {}",
+            match weighted_song.listed {
+                Some(Listed::Pinned) => "pinned, raised to the highest weight",
+                Some(Listed::Banned) => "banned, weight set to 0",
+                None => "no",
+            }
+        ),
+        format!("Weight:    {:.2}", weighted_song.weight),
+    ]
+}
+
+/// A weighted song as written by the json format
+#[derive(Serialize)]
+struct JsonWeight<'a> {
+    id: &'a str,
+    weight: f64,
+    rating: f32,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    added_at: Option<DateTime<Utc>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    name: Option<&'a str>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    artists: Option<&'a [String]>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    album: Option<&'a str>,
+}
+
+/// Formats the weights for output
+pub fn format_weights(
+    weighted_songs: &[WeightedSong],
+    format: &str,
+) -> Result<String, serde_json::Error> {
+    Ok(match format {
+        // format of the weighting plugin
+        "plugin" => weighted_songs
+            .iter()
+            .map(|weighted_song| format!("{}:{:.2}", weighted_song.song_id, weighted_song.weight))
+            .collect::<Vec<String>>()
+            .join("|"),
+        "json" => serde_json::to_string_pretty(
+            &weighted_songs
+                .iter()
+                .map(|weighted_song| {
+                    let metadata = weighted_song.time_rating.metadata.as_ref();
+                    JsonWeight {
+                        id: &weighted_song.song_id,
+                        weight: weighted_song.weight,
+                        rating: weighted_song.time_rating.rating,
+                        added_at: weighted_song.time_rating.added_at,
+                        name: metadata.map(|metadata| metadata.name.as_str()),
+                        artists: metadata.map(|metadata| metadata.artists.as_slice()),
+                        album: metadata.map(|metadata| metadata.album.as_str()),
+                    }
+                })
+                .collect::<Vec<_>>(),
+        )?,
+        "csv" => delimited(weighted_songs, ',', |field| {
+            // quote fields containing the delimiter, quotes or line breaks
+            if field.contains([',', '"', '\n', '\r']) {
+                format!("\"{}\"", field.replace('"', "\"\""))
+            } else {
+                field.to_string()
+            }
+        }),
+        "tsv" => delimited(weighted_songs, '\t', |field| {
+            field.replace(['\t', '\n', '\r'], " ")
+        }),
+        "explain" => explain_table(weighted_songs),
+        other => unreachable!("unknown format '{other}'"), // All formats listed
+    })
+}
+
+/// Formats the weights as a table with a header, escaping each field with `escape`
+fn delimited(
+    weighted_songs: &[WeightedSong],
+    delimiter: char,
+    escape: impl Fn(&str) -> String,
+) -> String {
+    let header = [
+        "id", "weight", "rating", "added_at", "name", "artists", "album",
+    ];
+
+    let mut lines = vec![header.join(&delimiter.to_string())];
+    for weighted_song in weighted_songs {
+        let metadata = weighted_song.time_rating.metadata.as_ref();
+        let fields = [
+            weighted_song.song_id.clone(),
+            format!("{:.2}", weighted_song.weight),
+            weighted_song.time_rating.rating.to_string(),
+            weighted_song
+                .time_rating
+                .added_at
+                .map_or_else(String::new, |added_at| added_at.to_rfc3339()),
+            metadata.map_or_else(String::new, |metadata| metadata.name.clone()),
+            metadata.map_or_else(String::new, |metadata| metadata.artists.join(", ")),
+            metadata.map_or_else(String::new, |metadata| metadata.album.clone()),
+        ];
+        lines.push(
+            fields
+                .iter()
+                .map(|field| escape(field))
+                .collect::<Vec<_>>()
+                .join(&delimiter.to_string()),
+        );
+    }
+
+    lines.join("\n")
+}
+
+/// Formats the weights and their components as an aligned table
+fn explain_table(weighted_songs: &[WeightedSong]) -> String {
+    let header = [
+        "rank",
+        "song",
+        "rating",
+        "tie",
+        "base",
+        "recency",
+        "staleness",
+        "artist",
+        "listed",
+        "weight",
+    ]
+    .map(String::from);
+
+    let rows: Vec<[String; 10]> = weighted_songs
+        .iter()
+        .map(|weighted_song| {
+            [
+                (weighted_song.rank + 1).to_string(),
+                display_name(weighted_song),
+                make_readable(weighted_song.time_rating.rating),
+                format!("{}/{}", weighted_song.tie_rank + 1, weighted_song.ties),
+                format!("{:.2}", weighted_song.base),
+                format!("x{:.2}", weighted_song.recency),
+                format!("x{:.2}", weighted_song.staleness),
+                format!("x{:.2}", weighted_song.artist_cap),
+                weighted_song
+                    .listed
+                    .map_or_else(String::new, |listed| listed.name().to_string()),
+                format!("{:.2}", weighted_song.weight),
+            ]
+        })
+        .collect();
+
+    // width of each column
+    let mut widths = header.clone().map(|field| field.chars().count());
+    for row in &rows {
+        for (width, field) in widths.iter_mut().zip(row) {
+            *width = (*width).max(field.chars().count());
+        }
+    }
+
+    std::iter::once(&header)
+        .chain(&rows)
+        .map(|row| {
+            row.iter()
+                .zip(widths)
+                .map(|(field, width)| format!("{field:<width$}"))
+                .collect::<Vec<_>>()
+                .join("  ")
+                .trim_end()
+                .to_string()
+        })
+        .collect::<Vec<_>>()
+        .join("\n")
+}